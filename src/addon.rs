@@ -0,0 +1,191 @@
+use crate::*;
+
+// Optional companion contract deployed by the store on a sub-account
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AddonKind {
+    GiftCard,
+    Membership,
+    Dao,
+}
+
+impl AddonKind {
+    // sub-account prefix the add-on is deployed on, e.g. `giftcard.<store>`
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            AddonKind::GiftCard => "giftcard",
+            AddonKind::Membership => "membership",
+            AddonKind::Dao => "dao",
+        }
+    }
+}
+
+// Arguments of the add-on, passed to its `new` method along with the store as owner
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AddonArgs {
+    // gift cards redeemable on the store
+    GiftCard { max_balance: U128, expiry: Option<u64> },
+    // paid subscription renewed every period (in milliseconds)
+    Membership { price: U128, period: u64 },
+    // council voting on store proposals, quorum is the number of votes needed
+    Dao { council: Vec<AccountId>, quorum: u32 },
+}
+
+impl AddonArgs {
+    pub fn kind(&self) -> AddonKind {
+        match self {
+            AddonArgs::GiftCard { .. } => AddonKind::GiftCard,
+            AddonArgs::Membership { .. } => AddonKind::Membership,
+            AddonArgs::Dao { .. } => AddonKind::Dao,
+        }
+    }
+
+    //panic if the arguments can't initialize the add-on
+    pub fn assert_valid(&self) {
+        match self {
+            AddonArgs::GiftCard { max_balance, .. } => {
+                require!(max_balance.0 > 0, "Gift card max balance must be above 0");
+            }
+            AddonArgs::Membership { price, period } => {
+                require!(price.0 > 0, "Membership price must be above 0");
+                require!(*period > 0, "Membership period must be above 0");
+            }
+            AddonArgs::Dao { council, quorum } => {
+                require!(!council.is_empty(), "DAO council cannot be empty");
+                let mut members = council.clone();
+                members.sort();
+                members.dedup();
+                require!(members.len() == council.len(), "DAO council has duplicate members");
+                require!(
+                    *quorum > 0 && *quorum as usize <= council.len(),
+                    "DAO quorum must be between 1 and the council size"
+                );
+            }
+        }
+    }
+}
+
+// Add-on contract deployed by the store
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StoreAddon {
+    pub kind: AddonKind,
+    pub account_id: AccountId,
+    pub code_version: String, // version of the registered code the add-on runs
+    pub deploy_mode: DeployMode,
+    pub status: DeploymentStatus,
+    pub deployed_at: u64, // Unix epoch in milliseconds
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Deploy a gift card, membership or DAO add-on on `<kind>.<store>` with an uploaded code version.
+    /// The add-on is initialized with `new`, taking the store as `owner_id` along with its arguments.
+    #[payable]
+    pub fn deploy_addon(&mut self, version: String, args: AddonArgs, deploy_mode: Option<DeployMode>) -> Promise {
+        self.assert_contract_owner();
+        args.assert_valid();
+
+        let kind = args.kind();
+        if let Some(addon) = self.addons.get(&kind) {
+            require!(
+                addon.status == DeploymentStatus::Failed,
+                "Add-on is already deployed or its deployment is in progress"
+            );
+        }
+
+        let code = self.code_blobs.get(&version).expect("No code uploaded for this version");
+        let current_account = env::current_account_id().to_string();
+        let subaccount: AccountId = format!("{}.{current_account}", kind.prefix()).parse().unwrap();
+        let deploy_mode = deploy_mode.unwrap_or(DeployMode::Locked);
+        require!(
+            deploy_mode != DeployMode::Upgradable,
+            "Add-ons can't be upgraded by the store"
+        );
+
+        let owner_id = env::current_account_id();
+        let init_args = match &args {
+            AddonArgs::GiftCard { max_balance, expiry } => serde_json::json!({
+                "owner_id": owner_id,
+                "max_balance": max_balance,
+                "expiry": expiry,
+            }),
+            AddonArgs::Membership { price, period } => serde_json::json!({
+                "owner_id": owner_id,
+                "price": price,
+                "period": period,
+            }),
+            AddonArgs::Dao { council, quorum } => serde_json::json!({
+                "owner_id": owner_id,
+                "council": council,
+                "quorum": quorum,
+            }),
+        };
+        let init_args = serde_json::to_vec(&init_args).unwrap();
+        let deploy_cost = self.internal_deploy_cost(&version, &init_args);
+        assert!(
+            env::attached_deposit() >= deploy_cost,
+            "To cover the storage required for your add-on contract, you need to attach at least {} yoctoNEAR to this transaction.",
+            deploy_cost
+        );
+
+        self.addons.insert(
+            &kind,
+            &StoreAddon {
+                kind: kind.clone(),
+                account_id: subaccount.clone(),
+                code_version: version,
+                deploy_mode: deploy_mode.clone(),
+                status: DeploymentStatus::Pending,
+                deployed_at: env::block_timestamp_ms(),
+            },
+        );
+
+        let mut promise = Promise::new(subaccount).create_account();
+        if deploy_mode == DeployMode::FullAccess {
+            promise = promise.add_full_access_key(env::signer_account_pk());
+        }
+
+        promise
+            .transfer(deploy_cost)
+            .deploy_contract(code)
+            .function_call("new".to_owned(), init_args, NO_DEPOSIT, GAGAS)
+            .then(Self::ext(env::current_account_id()).deploy_addon_callback(
+                kind,
+                env::predecessor_account_id(),
+                env::attached_deposit().into(),
+                deploy_cost.into(),
+            ))
+    }
+
+    #[private]
+    pub fn deploy_addon_callback(&mut self, kind: AddonKind, addon_creator_id: AccountId, attached_deposit: U128, deploy_cost: U128) {
+        let mut addon = self.addons.get(&kind).expect("No add-on deployment");
+        if is_promise_success() {
+            addon.status = DeploymentStatus::Deployed;
+            env::log_str("Successful add-on deployment");
+
+            // Refund what was attached above the deployment cost
+            let refund = attached_deposit.0 - deploy_cost.0;
+            if refund > 0 {
+                Promise::new(addon_creator_id).transfer(refund);
+            }
+        } else {
+            addon.status = DeploymentStatus::Failed;
+            Promise::new(addon_creator_id).transfer(attached_deposit.0);
+            env::log_str("failed add-on deployment")
+        }
+        self.addons.insert(&kind, &addon);
+    }
+
+    /// Get an add-on deployed by the store
+    pub fn get_addon(&self, kind: AddonKind) -> Option<StoreAddon> {
+        self.addons.get(&kind)
+    }
+
+    /// Get all the add-ons deployed by the store
+    pub fn get_addons(&self) -> Vec<StoreAddon> {
+        self.addons.values().collect()
+    }
+}
@@ -1,8 +1,12 @@
 use crate::*;
 
+// bounds of the length of an affiliate referral code
+const MIN_AFFILIATE_CODE_LEN: usize = 3;
+const MAX_AFFILIATE_CODE_LEN: usize = 32;
+
 #[near_bindgen]
 impl Contract {
-    //Requests from affiliates
+    //Requests from affiliates. Use the series ID 0 to apply to the store wide affiliate program
     #[payable]
     pub fn affiliate_request(&mut self, id: U64, affiliate_id: AccountId) {
         // Measure the initial storage being used on the contract
@@ -20,10 +24,20 @@ impl Contract {
         match self.affiliate_requests.get(check_existing as u64) {
             Some(a) => panic!("Already applied to become an affiliate: {:?}", a),
             None => {
-                // Get the series and how many tokens currently exist (edition number = cur_len + 1)
-                let series = self.series_by_id.get(&id.0).expect("Not a series");
+                // Requests for the store wide affiliate program use the reserved series ID
+                if id.0 == STORE_AFFILIATE_PROGRAM_ID {
+                    assert!(self.affiliate_program.get().is_some(), "This store has no affiliate program");
+                } else {
+                    // Get the series and how many tokens currently exist (edition number = cur_len + 1)
+                    let series = self.series_by_id.get(&id.0).expect("Not a series");
+
+                    assert!(series.affiliate.is_some(), "This series does not accept affiliate");
+                }
 
-                assert!(series.affiliate.is_some(), "This series does not accept affiliate");
+                assert!(
+                    !self.revoked_affiliates.contains(&(id.0, affiliate_id.clone())),
+                    "Affiliate was revoked for this series"
+                );
 
                 assert!(env::attached_deposit() > ONE_YOCTO, "Must attach upto 0.1 near to this call");
 
@@ -63,14 +77,23 @@ impl Contract {
 
         match self.affiliate_requests.get(index as u64) {
             Some(a) => {
-                let series = self.series_by_id.get(&id.0).expect("Not a series");
-
-                if let Some(mut affix) = series.affiliate {
+                // Store wide affiliates get their commission from the affiliate program
+                if id.0 == STORE_AFFILIATE_PROGRAM_ID {
                     assert!(
-                        affix.contains_key(&affiliate_id),
-                        "Affiliateer is already approved for this product"
+                        self.store_affiliates.insert(&affiliate_id),
+                        "Affiliateer is already approved for this store"
                     );
-                    affix.insert(affiliate_id, percentage);
+                } else {
+                    let mut series = self.series_by_id.get(&id.0).expect("Not a series");
+
+                    if let Some(affix) = series.affiliate.as_mut() {
+                        assert!(
+                            !affix.contains_key(&affiliate_id),
+                            "Affiliateer is already approved for this product"
+                        );
+                        affix.insert(affiliate_id, percentage);
+                    }
+                    self.series_by_id.insert(&id.0, &series);
                 }
 
                 {
@@ -91,9 +114,561 @@ impl Contract {
         }
     }
 
+    /// Reject a pending affiliate request. The storage deposit is refunded to the affiliate.
+    pub fn reject_affiliate(&mut self, id: U64, affiliate_id: AccountId, reason: Option<String>) -> AffiliatesRequests {
+        self.assert_contract_owner();
+
+        let index = self
+            .internal_find_affiliate_request(id, &affiliate_id)
+            .expect("Couldn't find affiliate request");
+
+        let request = self.affiliate_requests.get(index).unwrap();
+        assert!(!request.approved, "Affiliate is already approved, revoke instead");
+
+        self.internal_remove_affiliate_request(index, reason, EventLogVariant::AffiliateRequestRejected)
+    }
+
+    /// Withdraw a pending affiliate request. Must be called by the affiliate, the storage deposit is refunded.
+    pub fn withdraw_affiliate_request(&mut self, id: U64) -> AffiliatesRequests {
+        let affiliate_id = env::predecessor_account_id();
+
+        let index = self
+            .internal_find_affiliate_request(id, &affiliate_id)
+            .expect("Couldn't find affiliate request");
+
+        let request = self.affiliate_requests.get(index).unwrap();
+        assert!(!request.approved, "Affiliate is already approved and can't be withdrawn");
+
+        self.internal_remove_affiliate_request(index, None, EventLogVariant::AffiliateRequestWithdrawn)
+    }
+
+    /// Revoke an approved affiliate. The affiliate is removed from the series, refunded its storage deposit
+    /// and will be refused at mint time.
+    pub fn revoke_affiliate(&mut self, id: U64, affiliate_id: AccountId, reason: Option<String>) -> AffiliatesRequests {
+        self.assert_contract_owner();
+
+        let index = self
+            .internal_find_affiliate_request(id, &affiliate_id)
+            .expect("Couldn't find affiliate");
+
+        let request = self.affiliate_requests.get(index).unwrap();
+        assert!(request.approved, "Affiliate is not approved, reject instead");
+
+        if id.0 == STORE_AFFILIATE_PROGRAM_ID {
+            self.store_affiliates.remove(&affiliate_id);
+        } else {
+            let mut series = self.series_by_id.get(&id.0).expect("Not a series");
+            if let Some(affix) = series.affiliate.as_mut() {
+                affix.remove(&affiliate_id);
+            }
+            self.series_by_id.insert(&id.0, &series);
+        }
+
+        self.revoked_affiliates.insert(&(id.0, affiliate_id));
+
+        self.internal_remove_affiliate_request(index, reason, EventLogVariant::AffiliateRevoked)
+    }
+
+    /// Lift the revocation of an affiliate so that it can request to join the series again
+    pub fn reinstate_affiliate(&mut self, id: U64, affiliate_id: AccountId, reason: Option<String>) {
+        self.assert_contract_owner();
+
+        assert!(
+            self.revoked_affiliates.remove(&(id.0, affiliate_id.clone())),
+            "Affiliate was not revoked for this series"
+        );
+
+        let affiliate_log: EventLog = EventLog {
+            standard: STORE_STANDARD_NAME.to_string(),
+            version: STORE_EVENT_VERSION.to_string(),
+            event: EventLogVariant::AffiliateReinstated(vec![AffiliateLog {
+                account_id: affiliate_id.to_string(),
+                series_id: id.0,
+                //nothing is refunded, the revoked request was already removed
+                refund: U128(0),
+                memo: reason,
+            }]),
+        };
+        env::log_str(&affiliate_log.to_string());
+    }
+
+    /// Check if an affiliate was revoked for a series
+    pub fn is_affiliate_revoked(&self, id: U64, affiliate_id: AccountId) -> bool {
+        self.revoked_affiliates.contains(&(id.0, affiliate_id))
+    }
+
+    /// Set up or update the store wide affiliate program. Passing in none closes the program.
+    /// Percentages are in basis points (10000 = 100%).
+    pub fn set_affiliate_program(&mut self, program: Option<AffiliateProgram>) {
+        self.assert_contract_owner();
+
+        match program {
+            Some(mut program) => {
+                assert!(
+                    program.tiers.iter().all(|tier| tier.percentage <= 10_000)
+                        && program.series_overrides.values().all(|percentage| *percentage <= 10_000),
+                    "Commission percentage cannot be above 10000"
+                );
+                // keep the tiers sorted by volume
+                program.tiers.sort_by_key(|tier| tier.min_volume.0);
+                self.affiliate_program.set(&program);
+            }
+            None => {
+                self.affiliate_program.remove();
+            }
+        }
+    }
+
+    /// Get the store wide affiliate program
+    pub fn get_affiliate_program(&self) -> Option<AffiliateProgram> {
+        self.affiliate_program.get()
+    }
+
+    /// Check if a specified account is approved for the store wide affiliate program
+    pub fn is_store_affiliate(&self, account_id: AccountId) -> bool {
+        self.store_affiliates.contains(&account_id)
+    }
+
+    /// Register a referral code for the calling affiliate. Codes are case insensitive and unique across the store.
+    /// If a series ID is passed in the code is only valid for that series, otherwise it is valid for every series
+    /// the affiliate is approved for. Caller must attach enough $NEAR to cover storage.
+    #[payable]
+    pub fn register_affiliate_code(&mut self, code: String, series_id: Option<U64>) -> AffiliateCode {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let affiliate_id = env::predecessor_account_id();
+        let code = code.to_lowercase();
+
+        assert!(
+            code.len() >= MIN_AFFILIATE_CODE_LEN && code.len() <= MAX_AFFILIATE_CODE_LEN,
+            "Affiliate code must be between {} and {} characters",
+            MIN_AFFILIATE_CODE_LEN,
+            MAX_AFFILIATE_CODE_LEN
+        );
+        assert!(
+            code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "Affiliate code can only contain letters, numbers, '-' and '_'"
+        );
+        assert!(self.affiliate_codes.get(&code).is_none(), "Affiliate code is already taken");
+
+        // Ensure the caller is an approved affiliate for the series, or for any series if the code is store wide
+        if let Some(id) = series_id {
+            let series = self.series_by_id.get(&id.0).expect("Not a series");
+            assert!(
+                series.affiliate.is_some_and(|affix| affix.contains_key(&affiliate_id))
+                    || self.store_affiliates.contains(&affiliate_id),
+                "Affiliateer was not approved"
+            );
+        } else {
+            assert!(
+                self.affiliate_requests
+                    .iter()
+                    .any(|a| a.account_id == affiliate_id && a.approved),
+                "Affiliateer was not approved"
+            );
+        }
+
+        let affiliate_code = AffiliateCode {
+            code: code.clone(),
+            account_id: affiliate_id,
+            series_id,
+            active: true,
+        };
+        self.affiliate_codes.insert(&code, &affiliate_code);
+
+        //calculate the required storage which was the used - initial
+        let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
+
+        // refund storage used
+        refund_deposit(required_storage_in_bytes);
+
+        affiliate_code
+    }
+
+    /// Deactivate a referral code. Can be called by the affiliate that owns the code or the contract owner.
+    pub fn deactivate_affiliate_code(&mut self, code: String) -> AffiliateCode {
+        let code = code.to_lowercase();
+        let mut affiliate_code = self.affiliate_codes.get(&code).expect("Unknown affiliate code");
+
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == affiliate_code.account_id || caller == self.owner_id,
+            "only the affiliate or contract owner"
+        );
+
+        affiliate_code.active = false;
+        self.affiliate_codes.insert(&code, &affiliate_code);
+
+        affiliate_code
+    }
+
+    /// Look up a referral code
+    pub fn get_affiliate_code(&self, code: String) -> Option<AffiliateCode> {
+        self.affiliate_codes.get(&code.to_lowercase())
+    }
+
+    /// Get all the referral codes registered by an affiliate
+    pub fn get_affiliate_codes_for_account(&self, account_id: AccountId) -> Vec<AffiliateCode> {
+        self.affiliate_codes
+            .values()
+            .filter(|c| c.account_id == account_id)
+            .collect()
+    }
+
+    /// Register on-chain that the caller was referred by an affiliate, either by account ID or referral code.
+    /// Purchases of the caller made within the store's attribution window are attributed to that affiliate.
+    /// Caller must attach enough $NEAR to cover storage.
+    #[payable]
+    pub fn register_referral(&mut self, affiliate_id: Option<AccountId>, affiliate_code: Option<String>) -> Referral {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let buyer_id = env::predecessor_account_id();
+
+        // Resolve the affiliate from the referral code or ensure the passed in affiliate was approved
+        let affiliate_code = affiliate_code.map(|code| code.to_lowercase());
+        let affiliate_id = if let Some(code) = &affiliate_code {
+            let registered = self.affiliate_codes.get(code).expect("Unknown affiliate code");
+            assert!(registered.active, "Affiliate code is no longer active");
+            registered.account_id
+        } else {
+            let affiliate_id = affiliate_id.expect("Must pass in an affiliate or a referral code");
+            assert!(
+                self.store_affiliates.contains(&affiliate_id)
+                    || self
+                        .affiliate_requests
+                        .iter()
+                        .any(|a| a.account_id == affiliate_id && a.approved),
+                "Affiliateer was not approved"
+            );
+            affiliate_id
+        };
+        assert!(affiliate_id != buyer_id, "Affiliate cannot refer themselves");
+
+        // Only count a click when the buyer is referred by a new affiliate
+        let is_new_referral = self
+            .referrals
+            .get(&buyer_id)
+            .is_none_or(|referral| referral.affiliate_id != affiliate_id);
+        if is_new_referral {
+            let mut earnings = self.affiliate_earnings.get(&affiliate_id).unwrap_or_default();
+            earnings.clicks += 1;
+            self.affiliate_earnings.insert(&affiliate_id, &earnings);
+        }
+
+        let referral = Referral {
+            affiliate_id,
+            affiliate_code,
+            registered_at: env::block_timestamp_ms(),
+        };
+        self.referrals.insert(&buyer_id, &referral);
+
+        //calculate the required storage which was the used - initial
+        let required_storage_in_bytes = env::storage_usage().saturating_sub(initial_storage_usage);
+
+        // refund storage used
+        refund_deposit(required_storage_in_bytes);
+
+        referral
+    }
+
+    /// Set how long (in milliseconds) a registered referral is attributed to the affiliate. Passing in none keeps referrals forever.
+    pub fn set_attribution_window(&mut self, attribution_window: Option<u64>) {
+        self.assert_contract_owner();
+        self.attribution_window = attribution_window;
+    }
+
+    /// Get the referral registered by a buyer
+    pub fn get_referral(&self, account_id: AccountId) -> Option<Referral> {
+        self.referrals.get(&account_id)
+    }
+
+    /// Get the performance of an affiliate: clicks, conversions, revenue, commission and refund rate
+    pub fn get_affiliate_stats(&self, account_id: AccountId) -> AffiliateStats {
+        let earnings = self.affiliate_earnings.get(&account_id).unwrap_or_default();
+        let conversions = earnings.conversions();
+        let refund_rate = (earnings.refunds * 10_000)
+            .checked_div(conversions)
+            .unwrap_or(0) as u32;
+
+        AffiliateStats {
+            account_id,
+            clicks: earnings.clicks,
+            conversions,
+            revenue: U128(earnings.volume()),
+            commission: U128(earnings.accrued),
+            refunds: earnings.refunds,
+            refund_rate,
+        }
+    }
+
+    /// Withdraw the commission accrued by the caller. Everything available is withdrawn if no amount is passed in.
+    #[payable]
+    pub fn affiliate_withdraw(&mut self, amount: Option<U128>) -> Promise {
+        //assert that the user attached exactly 1 yoctoNEAR for security reasons
+        assert_one_yocto();
+
+        let affiliate_id = env::predecessor_account_id();
+        let mut earnings = self
+            .affiliate_earnings
+            .get(&affiliate_id)
+            .expect("No earnings for this affiliate");
+
+        let available = earnings.available();
+        let amount: Balance = amount.map(|a| a.into()).unwrap_or(available);
+        assert!(amount > 0, "Nothing to withdraw");
+        assert!(
+            amount <= available,
+            "Only {} yoctoNEAR available to withdraw",
+            available
+        );
+
+        earnings.paid += amount;
+        self.affiliate_earnings.insert(&affiliate_id, &earnings);
+
+        Promise::new(affiliate_id.clone())
+            .transfer(amount)
+            .then(
+                Self::ext(env::current_account_id())
+                    .affiliate_withdraw_callback(affiliate_id, U128(amount)),
+            )
+    }
+
+    #[private]
+    pub fn affiliate_withdraw_callback(&mut self, affiliate_id: AccountId, amount: U128) -> U128 {
+        if is_promise_success() {
+            env::log_str("Successful affiliate withdrawal");
+            amount
+        } else {
+            // Put the commission back into the affiliate's ledger
+            let mut earnings = self.affiliate_earnings.get(&affiliate_id).unwrap_or_default();
+            earnings.paid -= amount.0;
+            self.affiliate_earnings.insert(&affiliate_id, &earnings);
+            env::log_str("failed affiliate withdrawal");
+            U128(0)
+        }
+    }
+
+    /// Claw back the affiliate commission of a refunded sale. Called by the marketplace when it refunds a buyer.
+    /// The part of the commission that was not withdrawn yet is returned to the marketplace.
+    pub fn affiliate_clawback(&mut self, token_id: TokenId) -> U128 {
+        //ensure smart contract is only called by pipar marketplace
+        self.assert_marketplace_contract();
+
+        let mut receipt = self.receipts.get(&token_id).expect("No receipt for token");
+        assert!(!receipt.affiliate_clawed_back, "Commission was already clawed back");
+
+        let affiliate_id = receipt.affiliate_id.clone().expect("Sale has no affiliate");
+        let commission: Balance = receipt.affiliate_commission.map(|c| c.into()).unwrap_or(0);
+        let series_id = self.tokens_by_id.get(&token_id).expect("No token").series_id;
+
+        let mut earnings = self.affiliate_earnings.get(&affiliate_id).unwrap_or_default();
+        // only what is still held by the store can be returned, the withdrawn part is not recorded as clawed back
+        let returned = commission.min(earnings.available());
+        earnings.clawed_back += returned;
+        earnings.refunds += 1;
+        let series_earnings = earnings.series.entry(series_id).or_default();
+        series_earnings.clawed_back = U128(series_earnings.clawed_back.0 + returned);
+        self.affiliate_earnings.insert(&affiliate_id, &earnings);
+
+        receipt.affiliate_clawed_back = true;
+        self.receipts.insert(&token_id, &receipt);
+
+        if returned > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(returned);
+        }
+
+        U128(returned)
+    }
+
+    /// Get the commission ledger of an affiliate
+    pub fn get_affiliate_earnings(&self, account_id: AccountId) -> JsonAffiliateEarnings {
+        let earnings = self.affiliate_earnings.get(&account_id).unwrap_or_default();
+
+        JsonAffiliateEarnings {
+            available: U128(earnings.available()),
+            accrued: U128(earnings.accrued),
+            paid: U128(earnings.paid),
+            clawed_back: U128(earnings.clawed_back),
+            account_id,
+        }
+    }
+
+    /// Get the earnings of an affiliate broken down per series
+    pub fn get_affiliate_earnings_per_series(&self, account_id: AccountId) -> HashMap<SeriesId, AffiliateSeriesEarnings> {
+        self.affiliate_earnings
+            .get(&account_id)
+            .map(|earnings| earnings.series)
+            .unwrap_or_default()
+    }
+
     pub fn get_affiliates(&self) -> Vec<AffiliatesRequests> {
         let affiliates: Vec<AffiliatesRequests> = self.affiliate_requests.iter().map(|x| x).collect();
 
         affiliates
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const PRICE: Balance = 10 * ONE_NEAR;
+
+    //sell a token of a priced series through an affiliate with a 10% commission and return its ID
+    fn affiliate_sale(contract: &mut Contract) -> TokenId {
+        let series_id = create_series(contract, None, None, Some(PRICE));
+
+        set_context("affiliate.near", ONE_NEAR);
+        contract.affiliate_request(U64(series_id), account("affiliate.near"));
+        set_context(OWNER, 0);
+        contract.approve_affiliate(U64(series_id), account("affiliate.near"), 1_000);
+
+        set_context(MARKETPLACE, ONE_NEAR);
+        contract.nft_mint(
+            U64(series_id),
+            account("buyer.near"),
+            U128(PRICE + ONE_NEAR),
+            "red".to_string(),
+            Some(account("affiliate.near")),
+            None,
+        );
+        format!("{}:1", series_id)
+    }
+
+    //approve affiliate.near on a series and register the "Summer-Sale" code for it
+    fn coded_series(contract: &mut Contract) -> u64 {
+        let series_id = create_series(contract, None, None, Some(PRICE));
+
+        set_context("affiliate.near", ONE_NEAR);
+        contract.affiliate_request(U64(series_id), account("affiliate.near"));
+        set_context(OWNER, 0);
+        contract.approve_affiliate(U64(series_id), account("affiliate.near"), 1_000);
+
+        set_context("affiliate.near", ONE_NEAR);
+        contract.register_affiliate_code("Summer-Sale".to_string(), Some(U64(series_id)));
+        series_id
+    }
+
+    #[test]
+    fn affiliate_code_resolves_to_its_affiliate() {
+        let mut contract = new_contract();
+        let series_id = coded_series(&mut contract);
+        let affiliate = Some(account("affiliate.near"));
+
+        let code = "SUMMER-sale".to_string();
+        assert_eq!(contract.internal_resolve_affiliate(series_id, None, Some(&code)), affiliate);
+        assert_eq!(contract.internal_resolve_affiliate(series_id, affiliate.clone(), Some(&code)), affiliate);
+        //without a code the passed in affiliate is used as is
+        assert_eq!(contract.internal_resolve_affiliate(series_id, affiliate.clone(), None), affiliate);
+        assert_eq!(contract.internal_resolve_affiliate(series_id, None, None), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown affiliate code")]
+    fn affiliate_code_unknown() {
+        let mut contract = new_contract();
+        let series_id = coded_series(&mut contract);
+        contract.internal_resolve_affiliate(series_id, None, Some(&"winter-sale".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Affiliate code is no longer active")]
+    fn affiliate_code_deactivated() {
+        let mut contract = new_contract();
+        let series_id = coded_series(&mut contract);
+        contract.deactivate_affiliate_code("summer-sale".to_string());
+        contract.internal_resolve_affiliate(series_id, None, Some(&"summer-sale".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Affiliate code is not valid for this series")]
+    fn affiliate_code_for_another_series() {
+        let mut contract = new_contract();
+        let series_id = coded_series(&mut contract);
+        contract.internal_resolve_affiliate(series_id + 1, None, Some(&"summer-sale".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Affiliate code does not belong to the affiliate")]
+    fn affiliate_code_of_another_affiliate() {
+        let mut contract = new_contract();
+        let series_id = coded_series(&mut contract);
+        let code = "summer-sale".to_string();
+        contract.internal_resolve_affiliate(series_id, Some(account("other.near")), Some(&code));
+    }
+
+    #[test]
+    fn affiliate_clawback_returns_held_commission() {
+        let mut contract = new_contract();
+        let token_id = affiliate_sale(&mut contract);
+
+        set_context(MARKETPLACE, 0);
+        assert_eq!(contract.affiliate_clawback(token_id), U128(ONE_NEAR));
+
+        let earnings = contract.get_affiliate_earnings(account("affiliate.near"));
+        assert_eq!(earnings.clawed_back, U128(ONE_NEAR));
+        assert_eq!(earnings.available, U128(0));
+    }
+
+    #[test]
+    fn affiliate_clawback_records_only_returned_commission() {
+        let mut contract = new_contract();
+        let token_id = affiliate_sale(&mut contract);
+
+        set_context("affiliate.near", 1);
+        contract.affiliate_withdraw(Some(U128(ONE_NEAR / 4)));
+
+        set_context(MARKETPLACE, 0);
+        assert_eq!(contract.affiliate_clawback(token_id), U128(ONE_NEAR * 3 / 4));
+
+        let earnings = contract.get_affiliate_earnings(account("affiliate.near"));
+        assert_eq!(earnings.paid, U128(ONE_NEAR / 4));
+        assert_eq!(earnings.clawed_back, U128(ONE_NEAR * 3 / 4));
+        assert_eq!(earnings.available, U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Commission was already clawed back")]
+    fn affiliate_clawback_once() {
+        let mut contract = new_contract();
+        let token_id = affiliate_sale(&mut contract);
+
+        set_context(MARKETPLACE, 0);
+        contract.affiliate_clawback(token_id.clone());
+        contract.affiliate_clawback(token_id);
+    }
+
+    fn program() -> AffiliateProgram {
+        AffiliateProgram {
+            tiers: vec![
+                CommissionTier { min_volume: U128(0), percentage: 500 },
+                CommissionTier { min_volume: U128(1_000), percentage: 1_000 },
+            ],
+            series_overrides: HashMap::from([(2, 2_500)]),
+            max_commission_per_order: Some(U128(300)),
+            max_commission_per_period: Some(U128(500)),
+            period_duration: 1_000,
+        }
+    }
+
+    #[test]
+    fn affiliate_program_tiers() {
+        let program = program();
+        assert_eq!(program.percentage(1, 0), 500);
+        assert_eq!(program.percentage(1, 999), 500);
+        assert_eq!(program.percentage(1, 1_000), 1_000);
+        assert_eq!(program.percentage(2, 0), 2_500);
+    }
+
+    #[test]
+    fn affiliate_program_caps() {
+        let program = program();
+        assert_eq!(program.cap(100, 0), 100);
+        assert_eq!(program.cap(1_000, 0), 300);
+        assert_eq!(program.cap(300, 400), 100);
+        assert_eq!(program.cap(300, 500), 0);
+    }
+}
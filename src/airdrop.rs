@@ -0,0 +1,241 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Create an airdrop to every current holder of a series. Each holder gets either store tokens accrued on
+    /// their reward balance or a free token minted from another series. Caller must attach enough $NEAR to cover storage.
+    #[payable]
+    pub fn create_airdrop(&mut self, series_id: U64, kind: AirdropKind) -> u64 {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        self.assert_contract_owner();
+        require!(self.series_by_id.get(&series_id.0).is_some(), "Not a series");
+
+        match &kind {
+            AirdropKind::Reward { amount } => {
+                require!(amount.0 > 0, "Airdrop reward must be above 0");
+            }
+            AirdropKind::Mint { series_id: mint_series_id, color } => {
+                require!(
+                    mint_series_id.0 != series_id.0,
+                    "Cannot airdrop tokens of the series to its own holders"
+                );
+                let mint_series = self.series_by_id.get(&mint_series_id.0).expect("Not a series");
+                require!(mint_series.colors.contains_key(color), "Color is not present");
+            }
+        }
+
+        let airdrop_id = self.airdrops.len() + 1;
+        self.airdrops.insert(
+            &airdrop_id,
+            &Airdrop {
+                airdrop_id,
+                series_id,
+                kind,
+                cursor: 0,
+                recipients: 0,
+                completed: false,
+                created_at: env::block_timestamp_ms(),
+            },
+        );
+
+        //calculate the required storage which was the used - initial
+        let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
+
+        //refund any excess storage if the user attached too much. Panic if they didn't attach enough to cover the required.
+        refund_deposit(required_storage_in_bytes);
+
+        airdrop_id
+    }
+
+    /// Process the next `limit` tokens of the airdropped series (50 by default). Holders that already got the
+    /// airdrop are skipped, so the airdrop can be run across several transactions until it completes.
+    /// Caller must attach enough $NEAR to cover storage of the minted tokens.
+    #[payable]
+    pub fn run_airdrop(&mut self, airdrop_id: u64, limit: Option<u64>) -> Airdrop {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        self.assert_contract_owner();
+        let mut airdrop = self.airdrops.get(&airdrop_id).expect("No airdrop");
+        require!(!airdrop.completed, "Airdrop is already completed");
+
+        let series = self.series_by_id.get(&airdrop.series_id.0).expect("Not a series");
+        let tokens = series.tokens.as_vector();
+        let end = std::cmp::min(airdrop.cursor + limit.unwrap_or(50), tokens.len());
+
+        for index in airdrop.cursor..end {
+            let token_id = tokens.get(index).unwrap();
+            let owner_id = self.tokens_by_id.get(&token_id).expect("No token").owner_id;
+
+            // skip holders that own several tokens of the series
+            if self.airdrop_recipients.contains(&(airdrop_id, owner_id.clone())) {
+                continue;
+            }
+
+            match &airdrop.kind {
+                AirdropKind::Reward { amount } => {
+                    require!(
+                        self.reward_budget.is_none_or(|budget| budget >= amount.0),
+                        "Store reward budget is exhausted"
+                    );
+                    if let Some(budget) = self.reward_budget {
+                        self.reward_budget = Some(budget - amount.0);
+                    }
+                    self.internal_accrue_reward(&owner_id, amount.0);
+                }
+                AirdropKind::Mint { series_id, color } => {
+                    let mut mint_series = self.series_by_id.get(&series_id.0).expect("Not a series");
+                    // holders already at the holding limit of the airdropped series are skipped
+                    if self.internal_holding_limit_reached(series_id.0, &mint_series, &owner_id).is_some() {
+                        continue;
+                    }
+                    self.internal_mint(series_id.0, &mut mint_series, &owner_id, color.clone());
+                }
+            }
+            self.airdrop_recipients.insert(&(airdrop_id, owner_id));
+            airdrop.recipients += 1;
+        }
+
+        airdrop.cursor = end;
+        airdrop.completed = end == tokens.len();
+        self.airdrops.insert(&airdrop_id, &airdrop);
+
+        //calculate the required storage which was the used - initial
+        let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
+
+        //refund any excess storage if the user attached too much. Panic if they didn't attach enough to cover the required.
+        refund_deposit(required_storage_in_bytes);
+
+        airdrop
+    }
+
+    /// Stop an airdrop before it goes through all the holders of the series
+    pub fn cancel_airdrop(&mut self, airdrop_id: u64) {
+        self.assert_contract_owner();
+        let mut airdrop = self.airdrops.get(&airdrop_id).expect("No airdrop");
+        airdrop.completed = true;
+        self.airdrops.insert(&airdrop_id, &airdrop);
+    }
+
+    /// Get an airdrop and its progress
+    pub fn get_airdrop(&self, airdrop_id: u64) -> Option<Airdrop> {
+        self.airdrops.get(&airdrop_id)
+    }
+
+    /// Paginate through the airdrops of the store
+    pub fn get_airdrops(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Airdrop> {
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        self.airdrops
+            .values()
+            //skip to the index we specified in the start variable
+            .skip(start as usize)
+            //take the first "limit" elements in the vector. If we didn't specify a limit, use 50
+            .take(limit.unwrap_or(50) as usize)
+            .collect()
+    }
+
+    /// Check if a holder already got an airdrop
+    pub fn is_airdrop_recipient(&self, airdrop_id: u64, account_id: AccountId) -> bool {
+        self.airdrop_recipients.contains(&(airdrop_id, account_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    //series held twice by alice and once by bob
+    fn held_series(contract: &mut Contract) -> u64 {
+        let series_id = create_series(contract, None, None, None);
+        mint(contract, series_id, "alice.near");
+        mint(contract, series_id, "alice.near");
+        mint(contract, series_id, "bob.near");
+        series_id
+    }
+
+    fn run(contract: &mut Contract, series_id: u64, kind: AirdropKind) -> Airdrop {
+        set_context(OWNER, ONE_NEAR);
+        let airdrop_id = contract.create_airdrop(U64(series_id), kind);
+        contract.run_airdrop(airdrop_id, None)
+    }
+
+    #[test]
+    fn reward_airdrop_once_per_holder() {
+        let mut contract = new_contract();
+        let series_id = held_series(&mut contract);
+
+        let airdrop = run(&mut contract, series_id, AirdropKind::Reward { amount: U128(10) });
+        assert!(airdrop.completed);
+        assert_eq!(airdrop.recipients, 2);
+        assert_eq!(contract.get_reward_balance(account("alice.near")).amount, U128(10));
+        assert_eq!(contract.get_reward_balance(account("bob.near")).amount, U128(10));
+    }
+
+    #[test]
+    fn mint_airdrop_in_batches() {
+        let mut contract = new_contract();
+        let series_id = held_series(&mut contract);
+        let gift_id = create_series(&mut contract, None, None, None);
+
+        set_context(OWNER, ONE_NEAR);
+        let kind = AirdropKind::Mint { series_id: U64(gift_id), color: "red".to_string() };
+        let airdrop_id = contract.create_airdrop(U64(series_id), kind);
+
+        let airdrop = contract.run_airdrop(airdrop_id, Some(2));
+        assert!(!airdrop.completed);
+        assert_eq!(airdrop.recipients, 1);
+
+        let airdrop = contract.run_airdrop(airdrop_id, Some(2));
+        assert!(airdrop.completed);
+        assert_eq!(airdrop.recipients, 2);
+        assert_eq!(contract.nft_supply_for_owner(account("alice.near")), U128(3));
+        assert_eq!(contract.nft_supply_for_owner(account("bob.near")), U128(2));
+    }
+
+    #[test]
+    fn mint_airdrop_skips_holders_at_the_limit() {
+        let mut contract = new_contract();
+        let series_id = held_series(&mut contract);
+        let gift_id = create_series(&mut contract, None, None, None);
+        let rules = ResaleRules { max_resale_price: None, min_hold_period: None, max_per_account: Some(1) };
+        contract.set_series_resale_rules(U64(gift_id), Some(rules));
+        mint(&mut contract, gift_id, "alice.near");
+
+        let kind = AirdropKind::Mint { series_id: U64(gift_id), color: "red".to_string() };
+        let airdrop = run(&mut contract, series_id, kind);
+        assert_eq!(airdrop.recipients, 1);
+        assert!(!contract.is_airdrop_recipient(airdrop.airdrop_id, account("alice.near")));
+        assert!(contract.is_airdrop_recipient(airdrop.airdrop_id, account("bob.near")));
+        assert_eq!(contract.nft_supply_for_owner(account("alice.near")), U128(3));
+        assert_eq!(contract.nft_supply_for_owner(account("bob.near")), U128(2));
+    }
+
+    #[test]
+    fn mint_airdrop_approves_and_locks_for_the_marketplace() {
+        let mut contract = new_contract();
+        let series_id = held_series(&mut contract);
+        let gift_id = create_series(&mut contract, None, None, None);
+
+        let kind = AirdropKind::Mint { series_id: U64(gift_id), color: "red".to_string() };
+        run(&mut contract, series_id, kind);
+
+        let token_id = format!("{}:1", gift_id);
+        let token = contract.tokens_by_id.get(&token_id).unwrap();
+        assert!(token.approved_account_ids.contains_key(&account(MARKETPLACE)));
+        assert!(contract.tokens_locked.contains(&token_id));
+
+        //once the marketplace unlocks it the airdropped token can be resold
+        set_context(MARKETPLACE, 0);
+        contract.unlock_token(&token_id);
+        set_context(token.owner_id.as_str(), ONE_NEAR);
+        contract.list_token(token_id.clone(), U128(ONE_NEAR));
+        set_context("buyer.near", ONE_NEAR);
+        contract.buy_listing(token_id.clone(), 2);
+        assert_eq!(contract.tokens_by_id.get(&token_id).unwrap().owner_id, account("buyer.near"));
+    }
+}
@@ -13,10 +13,20 @@ pub struct JsonSeries {
     colors: HashMap<String, u32>,
     // Royalty used for all tokens in the collection
     royalty: Option<HashMap<AccountId, u32>>,
+    // Split of the first sale of each token
+    primary_split: Option<HashMap<AccountId, u32>>,
     // Token price
     price: Option<Balance>,
     // Owner of the collection
     owner_id: AccountId,
+    // Remaining store tokens that can be rewarded, unlimited if none
+    reward_budget: Option<U128>,
+    // Sale status
+    status: SeriesStatus,
+    // Only store token stakers with members access can buy it
+    members_only: bool,
+    // Anti-scalping rules applied to resales and transfers
+    resale_rules: Option<ResaleRules>,
 }
 
 #[near_bindgen]
@@ -126,8 +136,13 @@ impl Contract {
                 metadata: series.metadata,
                 colors: series.colors,
                 royalty: series.royalty,
+                primary_split: series.primary_split,
                 price: series.price,
                 owner_id: series.owner_id,
+                reward_budget: series.reward_budget.map(U128),
+                status: series.status,
+                members_only: series.members_only,
+                resale_rules: series.resale_rules,
             })
         } else {
             //if there isn't a series, we'll return None
@@ -135,6 +150,11 @@ impl Contract {
         }
     }
 
+    // get the sale receipt recorded when a token was bought
+    pub fn get_receipt(&self, token_id: TokenId) -> Option<MarketplaceData> {
+        self.receipts.get(&token_id)
+    }
+
     //get the total supply of NFTs on a current series
     pub fn nft_supply_for_series(&self, id: u64) -> U128 {
         //get the series
@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::fmt;
 
+use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize};
 
 /// Enum that represents the data type of the EventLog.
-/// The enum can either be an NftMint or an NftTransfer.
+/// The enum can either be an NftMint, an NftTransfer or one of the store events.
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "event", content = "data")]
 #[serde(rename_all = "snake_case")]
@@ -12,6 +14,11 @@ use near_sdk::serde::{Deserialize, Serialize};
 pub enum EventLogVariant {
     NftMint(Vec<NftMintLog>),
     NftTransfer(Vec<NftTransferLog>),
+    AffiliateRequestRejected(Vec<AffiliateLog>),
+    AffiliateRequestWithdrawn(Vec<AffiliateLog>),
+    AffiliateRevoked(Vec<AffiliateLog>),
+    AffiliateReinstated(Vec<AffiliateLog>),
+    RoyaltyUpdated(Vec<RoyaltyLog>),
 }
 
 /// Interface to capture data about an event
@@ -78,6 +85,43 @@ pub struct NftTransferLog {
     pub memo: Option<String>,
 }
 
+/// An event log to capture affiliate request rejections, withdrawals, revocations and reinstatements
+///
+/// Arguments
+/// * `account_id`: "affiliate.near"
+/// * `series_id`: 1
+/// * `refund`: storage deposit returned to the affiliate in yoctoNEAR
+/// * `memo`: optional message
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AffiliateLog {
+    pub account_id: String,
+    pub series_id: u64,
+    pub refund: U128,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+/// An event log to capture royalty changes of a series or a single token
+///
+/// Arguments
+/// * `series_id`: 1
+/// * `token_id`: "1:1", only set for per-token overrides
+/// * `royalty`: {"creator.near": 500}, none if the royalty was removed
+/// * `updated_by`: "owner.near"
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoyaltyLog {
+    pub series_id: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
+
+    pub royalty: Option<HashMap<String, u32>>,
+    pub updated_by: String,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,4 +179,52 @@ mod tests {
         };
         assert_eq!(expected, log.to_string());
     }
+
+    #[test]
+    fn store_format_affiliate_revoked() {
+        let expected = r#"EVENT_JSON:{"standard":"pipar_store","version":"1.0.0","event":"affiliate_revoked","data":[{"account_id":"affiliate.near","series_id":1,"refund":"1000"}]}"#;
+        let log = EventLog {
+            standard: "pipar_store".to_string(),
+            version: "1.0.0".to_string(),
+            event: EventLogVariant::AffiliateRevoked(vec![AffiliateLog {
+                account_id: "affiliate.near".to_string(),
+                series_id: 1,
+                refund: U128(1000),
+                memo: None,
+            }]),
+        };
+        assert_eq!(expected, log.to_string());
+    }
+
+    #[test]
+    fn store_format_affiliate_reinstated() {
+        let expected = r#"EVENT_JSON:{"standard":"pipar_store","version":"1.0.0","event":"affiliate_reinstated","data":[{"account_id":"affiliate.near","series_id":1,"refund":"0","memo":"appeal accepted"}]}"#;
+        let log = EventLog {
+            standard: "pipar_store".to_string(),
+            version: "1.0.0".to_string(),
+            event: EventLogVariant::AffiliateReinstated(vec![AffiliateLog {
+                account_id: "affiliate.near".to_string(),
+                series_id: 1,
+                refund: U128(0),
+                memo: Some("appeal accepted".to_string()),
+            }]),
+        };
+        assert_eq!(expected, log.to_string());
+    }
+
+    #[test]
+    fn store_format_royalty_updated() {
+        let expected = r#"EVENT_JSON:{"standard":"pipar_store","version":"1.0.0","event":"royalty_updated","data":[{"series_id":1,"token_id":"1:1","royalty":{"creator.near":500},"updated_by":"owner.near"}]}"#;
+        let log = EventLog {
+            standard: "pipar_store".to_string(),
+            version: "1.0.0".to_string(),
+            event: EventLogVariant::RoyaltyUpdated(vec![RoyaltyLog {
+                series_id: 1,
+                token_id: Some("1:1".to_string()),
+                royalty: Some(HashMap::from([("creator.near".to_string(), 500)])),
+                updated_by: "owner.near".to_string(),
+            }]),
+        };
+        assert_eq!(expected, log.to_string());
+    }
 }
@@ -1,4 +1,5 @@
 use crate::*;
+use near_sdk::PromiseResult;
 
 #[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -10,43 +11,215 @@ pub struct FtData {
     icon: String,
 }
 
+//serialize the arguments the store token is initialized with
+pub(crate) fn ft_init_args(total_supply: U128, name: String, symbol: String, icon: String) -> Vec<u8> {
+    serde_json::to_vec(&FtData {
+        owner_id: env::current_account_id(),
+        total_supply,
+        name,
+        symbol,
+        icon,
+    })
+    .unwrap()
+}
+
+// Token deployed by the store with deploy_token, the store contract is its owner
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StoreToken {
+    pub account_id: AccountId,
+    pub total_supply: U128,
+    pub name: String,
+    pub symbol: String,
+    pub icon: String,
+    pub code_version: String, // version of the registered code the token runs
+    pub deploy_mode: DeployMode,
+}
+
+// Access given to the token account when it is deployed
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DeployMode {
+    // no access key, the code can't be changed
+    Locked,
+    // no access key, the store upgrades the code through the `upgrade` method exported by the code
+    Upgradable,
+    // full access key of the signer added to the token account
+    FullAccess,
+}
+
+// State of a store token deployment
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DeploymentStatus {
+    Pending,
+    Deployed,
+    Failed,
+}
+
+// Sub-contract code uploaded to the store, deployed by version
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CodeVersion {
+    pub version: String,
+    pub hash: Base64VecU8, // sha256 hash of the code
+    pub size: u64, // code size in bytes
+    pub uploaded_by: AccountId,
+    pub uploaded_at: u64, // Unix epoch in milliseconds
+    pub upgradable: bool, // does the code export the `upgrade` method used by the store to upgrade it
+    pub metadata_updatable: bool, // does the code export the `set_metadata` method used by the store to update the token metadata
+}
+
 #[near_bindgen]
 impl Contract {
+    /// Upload a version of the sub-contract code deployed by the store, such as the fungible token.
+    /// Can be called by the marketplace or contract owner. Caller must attach enough $NEAR to cover storage.
+    /// `hash` is the expected sha256 of the code, the upload is refused if the code doesn't match it.
+    #[payable]
+    pub fn upload_code(&mut self, version: String, code: Base64VecU8, hash: Base64VecU8) -> CodeVersion {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.owner_id || caller == self.marketplace_contract_id,
+            "only the marketplace or contract owner"
+        );
+        require!(!version.is_empty(), "Version cannot be empty");
+        require!(
+            self.code_versions.get(&version).is_none(),
+            "Code version already exists"
+        );
+
+        let code: Vec<u8> = code.into();
+        require!(!code.is_empty(), "Code cannot be empty");
+        let code_hash = env::sha256(&code);
+        require!(code_hash == hash.0, "Code does not match the expected hash");
+
+        let code_version = CodeVersion {
+            version: version.clone(),
+            hash: Base64VecU8(code_hash),
+            size: code.len() as u64,
+            uploaded_by: caller,
+            uploaded_at: env::block_timestamp_ms(),
+            upgradable: wasm_exports_function(&code, "upgrade"),
+            metadata_updatable: wasm_exports_function(&code, "set_metadata"),
+        };
+        self.code_versions.insert(&version, &code_version);
+        self.code_blobs.insert(&version, &code);
+
+        //calculate the required storage which was the used - initial
+        let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
+
+        //refund any excess storage if the user attached too much. Panic if they didn't attach enough to cover the required.
+        refund_deposit(required_storage_in_bytes);
+
+        code_version
+    }
+
+    /// Remove an uploaded code version that neither the store token nor any add-on runs.
+    /// Can be called by the marketplace or contract owner, the released storage is refunded to the caller.
+    pub fn remove_code(&mut self, version: String) -> CodeVersion {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.owner_id || caller == self.marketplace_contract_id,
+            "only the marketplace or contract owner"
+        );
+        require!(
+            self.token_deployment != Some(DeploymentStatus::Pending),
+            "Token deployment is in progress"
+        );
+        require!(
+            self.store_token.as_ref().is_none_or(|token| token.code_version != version),
+            "Code version is run by the store token"
+        );
+        require!(
+            self.addons
+                .values()
+                .all(|addon| addon.code_version != version || addon.status == DeploymentStatus::Failed),
+            "Code version is run by an add-on"
+        );
+
+        let code_version = self.code_versions.remove(&version).expect("No code uploaded for this version");
+        self.code_blobs.remove(&version);
+
+        //refund the storage that was released to the caller
+        let released_storage_in_bytes = initial_storage_usage - env::storage_usage();
+        Promise::new(caller).transfer(env::storage_byte_cost() * Balance::from(released_storage_in_bytes));
+
+        code_version
+    }
+
+    /// Get an uploaded code version
+    pub fn get_code_version(&self, version: String) -> Option<CodeVersion> {
+        self.code_versions.get(&version)
+    }
+
+    /// Get all the uploaded code versions
+    pub fn get_code_versions(&self) -> Vec<CodeVersion> {
+        self.code_versions.values().collect()
+    }
+
+    /// Deploy the store token on `ft.<store>` with an uploaded code version.
+    /// The token account is locked by default, passing in `FullAccess` adds a full access key of the signer.
+    /// `Upgradable` lets the store upgrade the token and needs a code version that exports `upgrade`.
     #[payable]
     pub fn deploy_token(
         &mut self,
+        version: String,
         total_supply: U128,
         name: String,
         symbol: String,
         icon: String,
+        deploy_mode: Option<DeployMode>,
     ) -> Promise {
-        assert!(
-            env::attached_deposit() > TOKEN_BALANCE,
-            "To cover the storage required for your ft contract, you need to attach at least {} yoctoNEAR to this transaction.",
-            TOKEN_BALANCE
-        );
         self.assert_contract_token_false();
         self.assert_contract_owner();
+        require!(
+            self.token_deployment != Some(DeploymentStatus::Pending),
+            "Token deployment is already in progress"
+        );
+        let deploy_mode = deploy_mode.unwrap_or(DeployMode::Locked);
+        if deploy_mode == DeployMode::Upgradable {
+            self.internal_assert_upgradable(&version);
+        }
         let current_account = env::current_account_id().to_string();
         let subaccount: AccountId = format!("ft.{current_account}").parse().unwrap();
         assert!(
             env::is_valid_account_id(subaccount.as_bytes()),
             "Invalid subaccount"
         );
-        let init_args = serde_json::to_vec(&FtData {
-            owner_id: env::current_account_id(),
+        let code = self.code_blobs.get(&version).expect("No code uploaded for this version");
+        let store_token = StoreToken {
+            account_id: subaccount.clone(),
             total_supply,
-            name,
-            symbol,
-            icon,
-        })
-            .unwrap();
+            name: name.clone(),
+            symbol: symbol.clone(),
+            icon: icon.clone(),
+            code_version: version,
+            deploy_mode: deploy_mode.clone(),
+        };
+        let init_args = ft_init_args(total_supply, name, symbol, icon);
+        let deploy_cost = self.internal_deploy_cost(&store_token.code_version, &init_args);
+        assert!(
+            env::attached_deposit() >= deploy_cost,
+            "To cover the storage required for your ft contract, you need to attach at least {} yoctoNEAR to this transaction.",
+            deploy_cost
+        );
+
+        self.token_deployment = Some(DeploymentStatus::Pending);
+
+        let mut promise = Promise::new(subaccount.clone()).create_account();
+        if deploy_mode == DeployMode::FullAccess {
+            promise = promise.add_full_access_key(env::signer_account_pk());
+        }
 
-        Promise::new(subaccount.clone())
-            .create_account()
-            .add_full_access_key(env::signer_account_pk())
-            .transfer(TOKEN_BALANCE.into())
-            .deploy_contract(include_bytes!("../wasm/pipar_fungible_token.wasm").to_vec())
+        promise
+            .transfer(deploy_cost)
+            .deploy_contract(code)
             .function_call(
                 "new_default_meta".to_owned(),
                 init_args,
@@ -56,19 +229,337 @@ impl Contract {
             .then(Self::ext(env::current_account_id()).deploy_token_callback(
                 env::predecessor_account_id(),
                 env::attached_deposit().into(),
+                deploy_cost.into(),
+                store_token,
             ))
     }
 
     #[private]
-    pub fn deploy_token_callback(&mut self, token_creator_id: AccountId, attached_deposit: U128) {
+    pub fn deploy_token_callback(&mut self, token_creator_id: AccountId, attached_deposit: U128, deploy_cost: U128, store_token: StoreToken) {
         let attached_deposit: u128 = attached_deposit.into();
         if is_promise_success() {
-            self.token = true;
-            env::log_str("Successful token deployment")
+            let token_account = store_token.account_id.clone();
+            // The whole supply is minted to the store
+            self.store_token_treasury = store_token.total_supply.0;
+            self.store_token = Some(store_token);
+            self.token_deployment = Some(DeploymentStatus::Deployed);
+            env::log_str("Successful token deployment");
+
+            // Refund what was attached above the deployment cost
+            let refund = attached_deposit - deploy_cost.0;
+            if refund > 0 {
+                Promise::new(token_creator_id).transfer(refund);
+            }
+
+            // Use the deployed token as the reward currency unless the store already has one
+            if self.reward_token.is_none() {
+                self.internal_check_reward_token(token_account);
+            }
         } else {
+            self.token_deployment = Some(DeploymentStatus::Failed);
             Promise::new(token_creator_id).transfer(attached_deposit);
             env::log_str("failed token deployment")
         }
     }
 
-}
\ No newline at end of file
+    /// Upgrade a store token deployed `Upgradable` to an uploaded code version. The token code exposes an `upgrade` method,
+    /// callable by its owner, that deploys the code passed in as raw input. The new code must export it too.
+    pub fn upgrade_store_token(&mut self, version: String) -> Promise {
+        self.assert_contract_owner();
+        let token = self.store_token.clone().expect("Store owner has not deployed a token yet");
+        require!(
+            token.deploy_mode == DeployMode::Upgradable,
+            "Store token was not deployed upgradable"
+        );
+        require!(token.code_version != version, "Token already runs this code version");
+        self.internal_assert_upgradable(&version);
+        let code = self.code_blobs.get(&version).expect("No code uploaded for this version");
+
+        Promise::new(token.account_id)
+            .function_call("upgrade".to_owned(), code, NO_DEPOSIT, tgas(150))
+            .then(Self::ext(env::current_account_id()).upgrade_store_token_callback(version))
+    }
+
+    #[private]
+    pub fn upgrade_store_token_callback(&mut self, version: String) -> bool {
+        if is_promise_success() {
+            if let Some(token) = self.store_token.as_mut() {
+                token.code_version = version;
+            }
+            env::log_str("Successful token upgrade");
+            true
+        } else {
+            env::log_str("failed token upgrade");
+            false
+        }
+    }
+
+    /// Set the margin added to the code storage cost of deployments. Can only be called by the marketplace.
+    pub fn set_deploy_margin(&mut self, deploy_margin: U128) {
+        self.assert_marketplace_contract();
+        self.deploy_margin = deploy_margin.0;
+    }
+
+    /// Get the margin added to the code storage cost of deployments
+    pub fn get_deploy_margin(&self) -> U128 {
+        U128(self.deploy_margin)
+    }
+
+    /// Get the state of the last store token deployment
+    pub fn get_token_deployment(&self) -> Option<DeploymentStatus> {
+        self.token_deployment.clone()
+    }
+
+    /// Send store tokens held by the store contract (the token's treasury) to an account.
+    /// Tokens staked on the store are not part of the treasury and can't be sent.
+    #[payable]
+    pub fn store_token_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) -> Promise {
+        //assert that the user attached exactly 1 yoctoNEAR for security reasons
+        assert_one_yocto();
+        self.assert_contract_owner();
+        let token = self.store_token.clone().expect("Store owner has not deployed a token yet");
+        self.internal_use_treasury(&token.account_id, amount.0);
+
+        let mut promise = Promise::new(token.account_id.clone());
+
+        // Only pay for the receiver's storage on the token if it isn't registered yet
+        if !self
+            .reward_token_accounts
+            .contains(&(token.account_id.clone(), receiver_id.clone()))
+        {
+            let storage_args = serde_json::to_vec(&StorageData {
+                account_id: receiver_id.clone(),
+                registration_only: false,
+            })
+            .unwrap();
+            promise = promise.function_call("storage_deposit".to_owned(), storage_args, ONE_YOCTO, GAGAS);
+        }
+
+        let token_args = serde_json::to_vec(&TokenData {
+            receiver_id: receiver_id.clone(),
+            amount,
+            memo: memo.unwrap_or_default(),
+        })
+        .unwrap();
+
+        promise
+            .function_call("ft_transfer".to_owned(), token_args, 1, GAGAS)
+            .then(
+                Self::ext(env::current_account_id())
+                    .store_token_transfer_callback(token.account_id, receiver_id, amount),
+            )
+    }
+
+    #[private]
+    pub fn store_token_transfer_callback(&mut self, token_account_id: AccountId, receiver_id: AccountId, amount: U128) -> bool {
+        if is_promise_success() {
+            // The receiver is now registered on the token, no need to pay for its storage again
+            self.reward_token_accounts.insert(&(token_account_id, receiver_id));
+            env::log_str("Successful store token transfer");
+            true
+        } else {
+            self.internal_restore_treasury(&token_account_id, amount.0);
+            env::log_str("failed store token transfer");
+            false
+        }
+    }
+
+    /// Get the store token balance held by the store contract, the tokens staked on the store are part of it.
+    /// This is a change method as it calls `ft_balance_of` on the token, the callback returns the balance.
+    pub fn store_token_balance(&mut self) -> Promise {
+        let token = self.store_token.clone().expect("Store owner has not deployed a token yet");
+        let args = serde_json::to_vec(&serde_json::json!({ "account_id": env::current_account_id() })).unwrap();
+
+        Promise::new(token.account_id)
+            .function_call("ft_balance_of".to_owned(), args, NO_DEPOSIT, GAGAS)
+            .then(Self::ext(env::current_account_id()).store_token_balance_callback())
+    }
+
+    #[private]
+    pub fn store_token_balance_callback(&mut self) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+                .unwrap_or_else(|_| env::panic_str("Invalid store token balance")),
+            _ => env::panic_str("Could not get the store token balance"),
+        }
+    }
+
+    /// Update the metadata of the store token. The token must run a code version that exports `set_metadata`,
+    /// callable by its owner.
+    #[payable]
+    pub fn update_store_token_metadata(&mut self, name: Option<String>, symbol: Option<String>, icon: Option<String>) -> Promise {
+        //assert that the user attached exactly 1 yoctoNEAR for security reasons
+        assert_one_yocto();
+        self.assert_contract_owner();
+        let token = self.store_token.clone().expect("Store owner has not deployed a token yet");
+        let code_version = self
+            .code_versions
+            .get(&token.code_version)
+            .expect("No code uploaded for this version");
+        assert!(
+            code_version.metadata_updatable,
+            "Code version {} does not export a set_metadata method",
+            token.code_version
+        );
+
+        let args = serde_json::to_vec(&serde_json::json!({
+            "name": name,
+            "symbol": symbol,
+            "icon": icon,
+        }))
+        .unwrap();
+
+        Promise::new(token.account_id)
+            .function_call("set_metadata".to_owned(), args, NO_DEPOSIT, GAGAS)
+            .then(
+                Self::ext(env::current_account_id())
+                    .update_store_token_metadata_callback(name, symbol, icon),
+            )
+    }
+
+    #[private]
+    pub fn update_store_token_metadata_callback(&mut self, name: Option<String>, symbol: Option<String>, icon: Option<String>) -> Option<StoreToken> {
+        if is_promise_success() {
+            if let Some(token) = self.store_token.as_mut() {
+                if let Some(name) = name {
+                    token.name = name;
+                }
+                if let Some(symbol) = symbol {
+                    token.symbol = symbol;
+                }
+                if let Some(icon) = icon {
+                    token.icon = icon;
+                }
+            }
+            env::log_str("Successful store token metadata update");
+        } else {
+            env::log_str("failed store token metadata update");
+        }
+
+        self.store_token.clone()
+    }
+
+    /// Get the details of the token deployed by the store
+    pub fn get_store_token(&self) -> Option<StoreToken> {
+        self.store_token.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    // module with a single `upgrade` function
+    const UPGRADABLE_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, 0x02, 0x01, 0x00,
+        0x07, 0x0b, 0x01, 0x07, b'u', b'p', b'g', b'r', b'a', b'd', b'e', 0x00, 0x00,
+        0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b,
+    ];
+
+    fn upload(contract: &mut Contract, version: &str) -> CodeVersion {
+        set_context(OWNER, 10 * ONE_NEAR);
+        let hash = Base64VecU8(env::sha256(UPGRADABLE_WASM));
+        contract.upload_code(version.to_string(), Base64VecU8(UPGRADABLE_WASM.to_vec()), hash)
+    }
+
+    #[test]
+    fn code_exports_upgrade() {
+        assert!(wasm_exports_function(UPGRADABLE_WASM, "upgrade"));
+        assert!(!wasm_exports_function(UPGRADABLE_WASM, "new"));
+    }
+
+    #[test]
+    fn bundled_token_is_not_upgradable() {
+        let code = include_bytes!("../wasm/pipar_fungible_token.wasm");
+        assert!(wasm_exports_function(code, "ft_transfer"));
+        assert!(!wasm_exports_function(code, "upgrade"));
+    }
+
+    #[test]
+    fn malformed_code_exports_nothing() {
+        assert!(!wasm_exports_function(&UPGRADABLE_WASM[..24], "upgrade"));
+        assert!(!wasm_exports_function(&[0xff; 12], "upgrade"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Code version 1.0.0 does not export a set_metadata method")]
+    fn metadata_update_needs_set_metadata() {
+        let mut contract = new_contract();
+        set_context(OWNER, 10 * ONE_NEAR);
+        let code = include_bytes!("../wasm/pipar_fungible_token.wasm").to_vec();
+        let hash = Base64VecU8(env::sha256(&code));
+        let code_version = contract.upload_code("1.0.0".to_string(), Base64VecU8(code), hash);
+        assert!(!code_version.metadata_updatable);
+
+        contract.store_token = Some(StoreToken {
+            account_id: account("ft.store.near"),
+            total_supply: U128(1_000),
+            name: "Store".to_string(),
+            symbol: "STR".to_string(),
+            icon: String::new(),
+            code_version: "1.0.0".to_string(),
+            deploy_mode: DeployMode::Locked,
+        });
+        set_context(OWNER, 1);
+        contract.update_store_token_metadata(Some("Renamed".to_string()), None, None);
+    }
+
+    #[test]
+    fn deploy_cost_covers_the_init_args() {
+        let mut contract = new_contract();
+        upload(&mut contract, "1.0.0");
+
+        let cost = |icon: &str| {
+            contract.get_token_cost("1.0.0".to_string(), U128(1_000), "Store".to_string(), "STR".to_string(), icon.to_string()).0
+        };
+        let icon = format!("data:image/svg+xml;base64,{}", "A".repeat(10_000));
+        assert_eq!(cost(&icon) - cost(""), env::storage_byte_cost() * icon.len() as Balance);
+
+        let init_args = ft_init_args(U128(1_000), "Store".to_string(), "STR".to_string(), icon.clone());
+        let bytes = (UPGRADABLE_WASM.len() + init_args.len()) as Balance;
+        assert_eq!(cost(&icon), env::storage_byte_cost() * bytes + contract.deploy_margin);
+    }
+
+    #[test]
+    #[should_panic(expected = "Code does not match the expected hash")]
+    fn upload_code_checks_the_hash() {
+        let mut contract = new_contract();
+        set_context(OWNER, 10 * ONE_NEAR);
+        let hash = Base64VecU8(env::sha256(&UPGRADABLE_WASM[..24]));
+        contract.upload_code("1.0.0".to_string(), Base64VecU8(UPGRADABLE_WASM.to_vec()), hash);
+    }
+
+    #[test]
+    fn remove_unused_code() {
+        let mut contract = new_contract();
+        let code_version = upload(&mut contract, "1.0.0");
+        assert!(code_version.upgradable);
+
+        set_context(MARKETPLACE, 0);
+        contract.remove_code("1.0.0".to_string());
+        assert!(contract.get_code_version("1.0.0".to_string()).is_none());
+        assert!(contract.code_blobs.get(&"1.0.0".to_string()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Code version is run by the store token")]
+    fn remove_code_run_by_the_store_token() {
+        let mut contract = new_contract();
+        upload(&mut contract, "1.0.0");
+        contract.store_token = Some(StoreToken {
+            account_id: account("ft.store.near"),
+            total_supply: U128(1_000),
+            name: "Store".to_string(),
+            symbol: "STR".to_string(),
+            icon: String::new(),
+            code_version: "1.0.0".to_string(),
+            deploy_mode: DeployMode::Upgradable,
+        });
+
+        set_context(OWNER, 0);
+        contract.remove_code("1.0.0".to_string());
+    }
+}
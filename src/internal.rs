@@ -8,6 +8,85 @@ pub(crate) fn royalty_to_payout(royalty_percentage: u32, amount_to_pay: Balance)
     U128(royalty_percentage as u128 * amount_to_pay / 10_000u128)
 }
 
+//split an amount between the recipients of a split in basis points, the remainder goes to `remainder_id`
+pub(crate) fn split_payout(
+    split: &HashMap<AccountId, u32>,
+    remainder_id: &AccountId,
+    amount: Balance,
+) -> HashMap<AccountId, U128> {
+    let mut payout = HashMap::new();
+    let mut paid: Balance = 0;
+    for (account_id, percentage) in split.iter() {
+        //the remainder recipient gets their share with the remainder
+        if account_id == remainder_id {
+            continue;
+        }
+        let share = royalty_to_payout(*percentage, amount).0;
+        payout.insert(account_id.clone(), U128(share));
+        paid += share;
+    }
+    //the remainder takes all the rounding dust so the payout always sums up to the amount
+    let remainder = amount.checked_sub(paid).expect("Split is above 100%");
+    payout.insert(remainder_id.clone(), U128(remainder));
+    payout
+}
+
+//read an unsigned LEB128 integer of a wasm module, returns the value and the index after it
+fn read_leb128(code: &[u8], mut index: usize) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *code.get(index)?;
+        index += 1;
+        value |= u64::from(byte & 0x7f).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some((value, index));
+        }
+        shift += 7;
+    }
+}
+
+//check if a wasm module exports a function with the given name. Malformed code exports nothing
+pub(crate) fn wasm_exports_function(code: &[u8], name: &str) -> bool {
+    //skip the magic number and version
+    let mut index = 8;
+    while index < code.len() {
+        let section_id = code[index];
+        let (size, start) = match read_leb128(code, index + 1) {
+            Some(leb) => leb,
+            None => return false,
+        };
+        //the export section lists (name, kind, index) entries, functions are of kind 0
+        if section_id == 7 {
+            let (count, mut cursor) = match read_leb128(code, start) {
+                Some(leb) => leb,
+                None => return false,
+            };
+            for _ in 0..count {
+                let (len, name_start) = match read_leb128(code, cursor) {
+                    Some(leb) => leb,
+                    None => return false,
+                };
+                let name_end = name_start + len as usize;
+                let (export_name, kind) = match (code.get(name_start..name_end), code.get(name_end)) {
+                    (Some(export_name), Some(kind)) => (export_name, *kind),
+                    _ => return false,
+                };
+                if kind == 0 && export_name == name.as_bytes() {
+                    return true;
+                }
+                cursor = match read_leb128(code, name_end + 1) {
+                    Some((_, next)) => next,
+                    None => return false,
+                };
+            }
+            return false;
+        }
+        index = start + size as usize;
+    }
+    false
+}
+
 //calculate how many bytes the account ID is taking up
 pub(crate) fn bytes_for_approved_account_id(account_id: &AccountId) -> u64 {
     // The extra 4 bytes are coming from Borsh serialization to store the length of the string.
@@ -90,6 +169,695 @@ pub(crate) fn refund_deposit(storage_used: u64) {
 
 impl Contract {
 
+    //get the index of an affiliate request for the given series and affiliate
+    pub(crate) fn internal_find_affiliate_request(
+        &self,
+        id: U64,
+        affiliate_id: &AccountId,
+    ) -> Option<u64> {
+        self.affiliate_requests
+            .iter()
+            .position(|a| &a.account_id == affiliate_id && a.series_id == id)
+            .map(|index| index as u64)
+    }
+
+    //resolve the affiliate of a purchase from the passed in affiliate ID and/or referral code
+    pub(crate) fn internal_resolve_affiliate(
+        &self,
+        series_id: SeriesId,
+        affiliate: Option<AccountId>,
+        affiliate_code: Option<&String>,
+    ) -> Option<AccountId> {
+        //if there's no code, we simply use the passed in affiliate
+        let code = match affiliate_code {
+            Some(code) => code.to_lowercase(),
+            None => return affiliate,
+        };
+
+        let affiliate_code = self.affiliate_codes.get(&code).expect("Unknown affiliate code");
+        require!(affiliate_code.active, "Affiliate code is no longer active");
+        if let Some(code_series_id) = affiliate_code.series_id {
+            require!(
+                code_series_id.0 == series_id,
+                "Affiliate code is not valid for this series"
+            );
+        }
+        if let Some(affiliate_id) = affiliate {
+            require!(
+                affiliate_id == affiliate_code.account_id,
+                "Affiliate code does not belong to the affiliate"
+            );
+        }
+
+        Some(affiliate_code.account_id)
+    }
+
+    //get the loyalty tier reached by a buyer
+    pub(crate) fn internal_loyalty_tier(&self, buyer_id: &AccountId) -> Option<LoyaltyTier> {
+        let profile = self.customer_profiles.get(buyer_id)?;
+        let tiers = self.loyalty_tiers.get()?;
+        profile.tier(&tiers).cloned()
+    }
+
+    //get the best stake tier reached by one of the account's stake positions
+    pub(crate) fn internal_stake_tier(&self, account_id: &AccountId) -> Option<StakeTier> {
+        let tiers = self.stake_tiers.get()?;
+        let positions = self.stake_positions.get(account_id)?;
+        tiers
+            .into_iter()
+            .rev()
+            .find(|tier| positions.iter().any(|position| position.reaches(tier)))
+    }
+
+    //charge the storage used since initial_storage to the stake storage deposit of the account
+    pub(crate) fn internal_charge_stake_storage(&mut self, account_id: &AccountId, initial_storage: u64) {
+        let storage_used = env::storage_usage().saturating_sub(initial_storage);
+        let required_cost = env::storage_byte_cost() * Balance::from(storage_used);
+        let deposit = self.stake_storage.get(account_id).unwrap_or(0);
+        assert!(
+            required_cost <= deposit,
+            "Must deposit {} yoctoNEAR with stake_storage_deposit to cover the stake storage",
+            required_cost - deposit,
+        );
+        self.stake_storage.insert(account_id, &(deposit - required_cost));
+    }
+
+    //take store tokens out of the treasury, the staked funds can't be spent by the store
+    pub(crate) fn internal_use_treasury(&mut self, token_account_id: &AccountId, amount: Balance) {
+        if self.store_token.as_ref().is_none_or(|token| &token.account_id != token_account_id) {
+            return;
+        }
+        assert!(
+            amount <= self.store_token_treasury,
+            "Only {} store tokens are available outside of the staked funds",
+            self.store_token_treasury,
+        );
+        self.store_token_treasury -= amount;
+    }
+
+    //put store tokens back in the treasury after a failed transfer
+    pub(crate) fn internal_restore_treasury(&mut self, token_account_id: &AccountId, amount: Balance) {
+        if self.store_token.as_ref().is_some_and(|token| &token.account_id == token_account_id) {
+            self.store_token_treasury += amount;
+        }
+    }
+
+    //get the price a buyer pays for a token of the series after the loyalty or stake discount, whichever is higher
+    pub(crate) fn internal_price_for(&self, series: &Series, buyer_id: &AccountId) -> Option<Balance> {
+        let price = series.price?;
+        let loyalty_discount = self.internal_loyalty_tier(buyer_id).map_or(0, |tier| tier.discount);
+        let stake_discount = self.internal_stake_tier(buyer_id).map_or(0, |tier| tier.discount);
+        let discount = std::cmp::max(loyalty_discount, stake_discount);
+        Some(price - royalty_to_payout(discount, price).0)
+    }
+
+    //record a completed order on the buyer's profile
+    pub(crate) fn internal_record_purchase(&mut self, buyer_id: &AccountId, price: Balance) {
+        let now = env::block_timestamp_ms();
+        let mut profile = self.customer_profiles.get(buyer_id).unwrap_or(CustomerProfile {
+            orders: 0,
+            spent: U128(0),
+            first_purchase_at: now,
+            last_purchase_at: now,
+        });
+        profile.orders += 1;
+        profile.spent = U128(profile.spent.0 + price);
+        profile.last_purchase_at = now;
+        self.customer_profiles.insert(buyer_id, &profile);
+    }
+
+    //reward a buyer referred by another customer and their referrer on the buyer's first purchase
+    pub(crate) fn internal_buyer_referral_rewards(&mut self, buyer_id: &AccountId, receipt: &mut MarketplaceData) {
+        let program = match self.buyer_referral_program.get() {
+            Some(program) => program,
+            None => return,
+        };
+        if self.customer_profiles.get(buyer_id).is_some_and(|profile| profile.orders > 0) {
+            return;
+        }
+        let mut referral = match self.buyer_referrals.get(buyer_id) {
+            Some(referral) if !referral.rewarded => referral,
+            _ => return,
+        };
+
+        //both rewards are taken out of the store reward budget
+        let total = program.referrer_reward.0 + program.referee_reward.0;
+        if self.reward_budget.is_some_and(|budget| budget < total) {
+            env::log_str("Store reward budget is exhausted, no referral rewards");
+            return;
+        }
+        if let Some(budget) = self.reward_budget {
+            self.reward_budget = Some(budget - total);
+        }
+
+        if program.referee_reward.0 > 0 {
+            let reward_amount = receipt.reward_amount.map_or(0, |amount| amount.0);
+            receipt.reward_amount = Some(U128(reward_amount + program.referee_reward.0));
+        }
+        receipt.referrer_id = Some(referral.referrer_id.clone());
+        receipt.referrer_reward = Some(program.referrer_reward);
+
+        referral.rewarded = true;
+        self.buyer_referrals.insert(buyer_id, &referral);
+    }
+
+    //accrue store token rewards on an account's balance. An expired balance is expired first, it isn't revived
+    pub(crate) fn internal_accrue_reward(&mut self, account_id: &AccountId, amount: Balance) {
+        self.internal_expire_reward_balance(account_id);
+        let mut balance = self.reward_balances.get(account_id).unwrap_or(RewardBalance {
+            amount: U128(0),
+            last_accrued_at: 0,
+        });
+        balance.amount = U128(balance.amount.0 + amount);
+        balance.last_accrued_at = env::block_timestamp_ms();
+        self.reward_balances.insert(account_id, &balance);
+    }
+
+    //remove the reward balance of an account if it expired and return it to the store reward budget
+    pub(crate) fn internal_expire_reward_balance(&mut self, account_id: &AccountId) -> Balance {
+        let balance = match self.reward_balances.get(account_id) {
+            Some(balance) if self.internal_reward_balance_expired(&balance) => balance,
+            _ => return 0,
+        };
+        self.reward_balances.remove(account_id);
+        //without a budget, the store can reward an unlimited amount and there's nothing to return
+        if let Some(budget) = self.reward_budget {
+            self.reward_budget = Some(budget + balance.amount.0);
+        }
+        env::log_str(&format!("{} expired rewards of {}", balance.amount.0, account_id));
+
+        balance.amount.0
+    }
+
+    //check if a reward balance stayed unclaimed for longer than the store's reward expiry
+    pub(crate) fn internal_reward_balance_expired(&self, balance: &RewardBalance) -> bool {
+        self.reward_expiry
+            .is_some_and(|expiry| env::block_timestamp_ms() > balance.last_accrued_at + expiry)
+    }
+
+    //refuse to switch the reward token while rewards are still owed in the current one
+    pub(crate) fn internal_assert_no_outstanding_rewards(&self) {
+        require!(
+            self.outstanding_rewards.is_empty(),
+            "Rewards in the current reward token must be delivered or retried first"
+        );
+    }
+
+    //check the metadata of a NEP-141 token before registering it as the reward token
+    pub(crate) fn internal_check_reward_token(&mut self, token_account_id: AccountId) -> Promise {
+        Promise::new(token_account_id.clone())
+            .function_call("ft_metadata".to_owned(), Vec::new(), NO_DEPOSIT, GAGAS)
+            .then(Self::ext(env::current_account_id()).set_reward_token_callback(token_account_id))
+    }
+
+    //persist a new store token reward and return its ID
+    pub(crate) fn internal_add_reward(&mut self, receiver_id: AccountId, amount: Balance) -> u64 {
+        let reward_id = self.rewards.len() + 1;
+        let reward = PendingReward {
+            reward_id,
+            receiver_id,
+            amount: U128(amount),
+            status: RewardStatus::Pending,
+            attempts: 0,
+            created_at: env::block_timestamp_ms(),
+        };
+        self.rewards.insert(&reward_id, &reward);
+        self.outstanding_rewards.insert(&reward_id);
+
+        reward_id
+    }
+
+    //send a persisted reward to its receiver. The callback marks it as delivered or failed
+    pub(crate) fn internal_send_reward(&mut self, reward_id: u64) -> Promise {
+        //confirm store has a reward token
+        let token_account = self
+            .reward_token
+            .as_ref()
+            .map(|token| token.account_id.clone())
+            .expect("Store has no reward token yet");
+
+        let mut reward = self.rewards.get(&reward_id).expect("No reward");
+        self.internal_use_treasury(&token_account, reward.amount.0);
+        reward.status = RewardStatus::Pending;
+        reward.attempts += 1;
+        self.rewards.insert(&reward_id, &reward);
+
+        let mem = format!("Thank You for Shopping at {:?}!", env::current_account_id());
+
+        let mut promise = Promise::new(token_account.clone());
+
+        // Only pay for the receiver's storage on the token if it isn't registered yet
+        if !self.reward_token_accounts.contains(&(token_account, reward.receiver_id.clone())) {
+            let storage_args = serde_json::to_vec(&StorageData {
+                account_id: reward.receiver_id.clone(),
+                registration_only: false,
+            })
+            .unwrap();
+            promise = promise.function_call("storage_deposit".to_owned(), storage_args, ONE_YOCTO, GAGAS);
+        }
+
+        let token_args = serde_json::to_vec(&TokenData {
+            receiver_id: reward.receiver_id,
+            amount: reward.amount,
+            memo: mem,
+        })
+        .unwrap();
+
+        promise
+            .function_call("ft_transfer".to_owned(), token_args, 1, GAGAS)
+            .then(Self::ext(env::current_account_id()).reward_with_token_callback(reward_id))
+    }
+
+    //compute the store token reward of a purchase of the series
+    pub(crate) fn internal_compute_reward(
+        &self,
+        series: &Series,
+        buyer_id: &AccountId,
+        price: Balance,
+    ) -> Balance {
+        if !series.metadata.is_reward {
+            return 0;
+        }
+
+        //without a reward policy, buyers get the fixed reward of the series
+        let reward_per_unit = series.metadata.reward_amount_per_unit.0;
+        let reward = match self.reward_policy.get() {
+            Some(policy) => policy.reward(
+                reward_per_unit,
+                series.metadata.category.as_ref(),
+                price,
+                self.customer_profiles.get(buyer_id).is_none_or(|profile| profile.orders == 0),
+                env::block_timestamp_ms(),
+            ),
+            None => reward_per_unit,
+        };
+
+        //loyalty members get bigger rewards
+        let reward = match self.internal_loyalty_tier(buyer_id) {
+            Some(tier) => reward * tier.reward_multiplier as u128 / 10_000,
+            None => reward,
+        };
+
+        //rewards stop once the series or store budget runs out
+        reward
+            .min(series.reward_budget.unwrap_or(Balance::MAX))
+            .min(self.reward_budget.unwrap_or(Balance::MAX))
+    }
+
+    //take issued rewards out of the series and store reward budgets
+    pub(crate) fn internal_use_reward_budget(&mut self, series_id: SeriesId, amount: Balance) {
+        let mut series = self.series_by_id.get(&series_id).expect("Not a series");
+        if let Some(budget) = series.reward_budget {
+            series.reward_budget = Some(budget.saturating_sub(amount));
+            self.series_by_id.insert(&series_id, &series);
+        }
+
+        if let Some(budget) = self.reward_budget {
+            self.reward_budget = Some(budget.saturating_sub(amount));
+        }
+    }
+
+    //get the referral registered by a buyer if it can still be attributed to a purchase of the series
+    pub(crate) fn internal_attributed_referral(
+        &self,
+        buyer_id: &AccountId,
+        series_id: SeriesId,
+        series: &Series,
+    ) -> Option<Referral> {
+        let referral = self.referrals.get(buyer_id)?;
+
+        //the referral must have been registered within the attribution window
+        if let Some(window) = self.attribution_window {
+            if env::block_timestamp_ms() > referral.registered_at + window {
+                return None;
+            }
+        }
+
+        //the referral code must still be active and valid for the series
+        if let Some(code) = &referral.affiliate_code {
+            let affiliate_code = self.affiliate_codes.get(code)?;
+            if !affiliate_code.active
+                || affiliate_code.series_id.is_some_and(|id| id.0 != series_id)
+            {
+                return None;
+            }
+        }
+
+        //the affiliate must still be approved for the series or the store
+        let approved = series
+            .affiliate
+            .as_ref()
+            .is_some_and(|affix| affix.contains_key(&referral.affiliate_id))
+            || self.store_affiliates.contains(&referral.affiliate_id);
+        if !approved || self.revoked_affiliates.contains(&(series_id, referral.affiliate_id.clone())) {
+            return None;
+        }
+
+        Some(referral)
+    }
+
+    //get the commission percentage and amount of an affiliate for a sale. Panics if the affiliate was not approved
+    pub(crate) fn internal_affiliate_commission(
+        &self,
+        series_id: SeriesId,
+        series: &Series,
+        affiliate_id: &AccountId,
+        price: Balance,
+    ) -> Option<(u32, Balance)> {
+        let program = self.affiliate_program.get();
+        let earnings = self.affiliate_earnings.get(affiliate_id).unwrap_or_default();
+
+        //affiliates approved for the series keep the percentage they were approved with,
+        //otherwise fall back to the store wide affiliate program
+        let series_percentage = series
+            .affiliate
+            .as_ref()
+            .and_then(|affix| affix.get(affiliate_id).copied());
+        let percentage = if let Some(percentage) = series_percentage {
+            percentage
+        } else if self.store_affiliates.contains(affiliate_id) {
+            program
+                .as_ref()
+                .expect("Store has no affiliate program")
+                .percentage(series_id, earnings.volume())
+        } else if series.affiliate.is_some() {
+            env::panic_str("Affiliateer was not approved")
+        } else {
+            return None;
+        };
+
+        //cap the commission to the limits of the affiliate program
+        let mut commission = royalty_to_payout(percentage, price).0;
+        if let Some(program) = program {
+            let period_commission =
+                earnings.commission_in_period(env::block_timestamp_ms(), program.period_duration);
+            commission = program.cap(commission, period_commission);
+        }
+
+        Some((percentage, commission))
+    }
+
+    //credit the commission of an attributed sale to the affiliate's ledger
+    pub(crate) fn internal_credit_affiliate(
+        &mut self,
+        affiliate_id: &AccountId,
+        series_id: SeriesId,
+        price: Balance,
+        commission: Balance,
+    ) {
+        //get the ledger of the affiliate or start a new one
+        let mut earnings = self.affiliate_earnings.get(affiliate_id).unwrap_or_default();
+        earnings.accrued += commission;
+
+        //start a new cap period if the current one is over
+        let now = env::block_timestamp_ms();
+        let period_duration = self.affiliate_program.get().map_or(0, |p| p.period_duration);
+        if now >= earnings.period_started_at + period_duration {
+            earnings.period_started_at = now;
+            earnings.period_commission = 0;
+        }
+        earnings.period_commission += commission;
+
+        //update the breakdown for the series
+        let series_earnings = earnings.series.entry(series_id).or_default();
+        series_earnings.sales += 1;
+        series_earnings.revenue = U128(series_earnings.revenue.0 + price);
+        series_earnings.accrued = U128(series_earnings.accrued.0 + commission);
+
+        self.affiliate_earnings.insert(affiliate_id, &earnings);
+    }
+
+    //remove an affiliate request, refund the storage it released to the affiliate and log the event
+    pub(crate) fn internal_remove_affiliate_request(
+        &mut self,
+        index: u64,
+        memo: Option<String>,
+        event: fn(Vec<AffiliateLog>) -> EventLogVariant,
+    ) -> AffiliatesRequests {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let request = self.affiliate_requests.swap_remove(index);
+
+        //calculate the storage which was released by removing the request
+        let released_storage_in_bytes = initial_storage_usage - env::storage_usage();
+        let refund = env::storage_byte_cost() * Balance::from(released_storage_in_bytes);
+
+        //refund the storage deposit to the affiliate
+        if refund > 0 {
+            Promise::new(request.account_id.clone()).transfer(refund);
+        }
+
+        // Construct the affiliate log as per the events standard.
+        let affiliate_log: EventLog = EventLog {
+            // Standard name ("pipar_store").
+            standard: STORE_STANDARD_NAME.to_string(),
+            // Version of the store events ("1.0.0").
+            version: STORE_EVENT_VERSION.to_string(),
+            // The data related with the event stored in a vector.
+            event: event(vec![AffiliateLog {
+                // The affiliate account ID.
+                account_id: request.account_id.to_string(),
+                // The series the request was made for.
+                series_id: request.series_id.0,
+                // The storage deposit returned to the affiliate.
+                refund: U128(refund),
+                // An optional memo to include.
+                memo,
+            }]),
+        };
+
+        // Log the serialized json.
+        env::log_str(&affiliate_log.to_string());
+
+        request
+    }
+
+    //ensure a token of the series can be resold at the given price
+    pub(crate) fn internal_assert_resale_price(&self, series: &Series, price: Balance) {
+        let max_price = series
+            .resale_rules
+            .as_ref()
+            .and_then(|rules| rules.max_price(series.price));
+        if let Some(max_price) = max_price {
+            assert!(
+                price <= max_price,
+                "Resale price of {} is above the maximum of {} for this series",
+                price,
+                max_price
+            );
+        }
+    }
+
+    //get the holding limit of the series if the account already holds that many of its tokens
+    pub(crate) fn internal_holding_limit_reached(&self, series_id: SeriesId, series: &Series, account_id: &AccountId) -> Option<u32> {
+        let max_per_account = series.resale_rules.as_ref().and_then(|rules| rules.max_per_account)?;
+        let held = self.series_holdings.get(&(series_id, account_id.clone())).unwrap_or(0);
+
+        if held >= max_per_account {
+            Some(max_per_account)
+        } else {
+            None
+        }
+    }
+
+    //ensure the account can hold one more token of the series
+    pub(crate) fn internal_assert_holding_limit(&self, series_id: SeriesId, series: &Series, account_id: &AccountId) {
+        if let Some(max_per_account) = self.internal_holding_limit_reached(series_id, series, account_id) {
+            env::panic_str(&format!(
+                "{} already holds the maximum of {} tokens of series {}",
+                account_id, max_per_account, series_id
+            ));
+        }
+    }
+
+    //remove a resale listing and refund the released storage to the account that listed it
+    pub(crate) fn internal_remove_listing(&mut self, token_id: &TokenId) -> Option<Listing> {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let listing = self.listings.remove(token_id)?;
+
+        //remove the token from the listings of its series, dropping the set if it's now empty
+        if let Some(mut listings_set) = self.listings_per_series.get(&listing.series_id.0) {
+            listings_set.remove(token_id);
+            if listings_set.is_empty() {
+                self.listings_per_series.remove(&listing.series_id.0);
+            } else {
+                self.listings_per_series.insert(&listing.series_id.0, &listings_set);
+            }
+        }
+
+        //calculate the storage which was released by removing the listing
+        let released_storage_in_bytes = initial_storage_usage.saturating_sub(env::storage_usage());
+        let refund = env::storage_byte_cost() * Balance::from(released_storage_in_bytes);
+
+        //refund the storage deposit to the account that listed the token
+        if refund > 0 {
+            Promise::new(listing.owner_id.clone()).transfer(refund);
+        }
+
+        Some(listing)
+    }
+
+    //get the royalty of a token, its own override or the royalty of its series
+    pub(crate) fn internal_royalty_for(&self, token_id: &TokenId, series: &Series) -> Option<HashMap<AccountId, u32>> {
+        self.token_royalties.get(token_id).or_else(|| series.royalty.clone())
+    }
+
+    //ensure a new royalty is within the store limits and not above the royalty at mint time
+    pub(crate) fn internal_assert_royalty_update(&self, max_royalty: u32, royalty: &HashMap<AccountId, u32>) {
+        self.royalty_limits.assert_valid(royalty);
+        let total: u32 = royalty.values().sum();
+        assert!(
+            total <= max_royalty,
+            "Royalty total of {} is above the {} set at mint time",
+            total,
+            max_royalty
+        );
+    }
+
+    //log a royalty change of a series or a single token
+    pub(crate) fn internal_log_royalty_update(
+        &self,
+        series_id: SeriesId,
+        token_id: Option<TokenId>,
+        royalty: &Option<HashMap<AccountId, u32>>,
+    ) {
+        let log = EventLog {
+            standard: STORE_STANDARD_NAME.to_string(),
+            version: STORE_EVENT_VERSION.to_string(),
+            event: EventLogVariant::RoyaltyUpdated(vec![RoyaltyLog {
+                series_id,
+                token_id,
+                royalty: royalty.as_ref().map(|royalty| {
+                    royalty
+                        .iter()
+                        .map(|(account_id, percentage)| (account_id.to_string(), *percentage))
+                        .collect()
+                }),
+                updated_by: env::predecessor_account_id().to_string(),
+            }]),
+        };
+        env::log_str(&log.to_string());
+    }
+
+    //get the deposit needed to deploy a sub-contract with an uploaded code version and initialize it with the
+    //serialized init args, which the sub-contract stores along with its code
+    pub(crate) fn internal_deploy_cost(&self, version: &String, init_args: &[u8]) -> Balance {
+        let code_version = self.code_versions.get(version).expect("No code uploaded for this version");
+        let bytes = Balance::from(code_version.size) + init_args.len() as Balance;
+        env::storage_byte_cost() * bytes + self.deploy_margin
+    }
+
+    //ensure a code version exports the `upgrade` method the store uses to upgrade it
+    pub(crate) fn internal_assert_upgradable(&self, version: &String) {
+        let code_version = self.code_versions.get(version).expect("No code uploaded for this version");
+        assert!(
+            code_version.upgradable,
+            "Code version {} does not export an upgrade method",
+            version
+        );
+    }
+
+    //mint the next token of a series in the given color to the receiver, approve and lock it for the marketplace
+    //and return its ID
+    pub(crate) fn internal_mint(
+        &mut self,
+        series_id: SeriesId,
+        series: &mut Series,
+        receiver_id: &AccountId,
+        color: String,
+    ) -> TokenId {
+        // Ensure the receiver is below the holding limit of the series
+        self.internal_assert_holding_limit(series_id, series, receiver_id);
+
+        let cur_len = series.tokens.len();
+        // Ensure we haven't overflowed on the number of copies minted
+        if let Some(copies) = series.metadata.copies {
+            require!(
+                cur_len < copies,
+                "cannot mint anymore NFTs for the given series. Limit reached"
+            );
+        }
+
+        assert!(
+            series.colors.contains_key(&color),
+            "Color is not present"
+        );
+
+        if let Some(cur_color_len) = series.colors.get(&color) {
+            assert!(
+                cur_color_len >= &1,
+                "This color for product is not available, choose another color"
+            );
+
+            series.colors.insert(color, cur_color_len - &1);
+        }
+
+        // The token ID is stored internally as `${series_id}:${edition}`
+        let token_id = format!("{}:{}", series_id, cur_len + 1);
+        series.tokens.insert(&token_id);
+        //the series royalty can't be raised above the royalty of any minted token
+        let royalty_at_mint: u32 = series.royalty.as_ref().map_or(0, |royalty| royalty.values().sum());
+        series.max_royalty = std::cmp::min(series.max_royalty, royalty_at_mint);
+        self.series_by_id.insert(&series_id, series);
+
+        //specify the token struct that contains the owner ID
+        let token = Token {
+            // Series ID that the token belongs to
+            series_id,
+            //set the owner ID equal to the receiver ID passed into the function
+            owner_id: receiver_id.clone(),
+            //we set the approved account IDs to the default value (an empty map)
+            approved_account_ids: Default::default(),
+            //the next approval ID is set to 0
+            next_approval_id: 0,
+            //the receiver gets the token now
+            acquired_at: env::block_timestamp_ms(),
+            royalty_at_mint,
+        };
+
+        //insert the token ID and token struct and make sure that the token doesn't exist
+        require!(
+            self.tokens_by_id.insert(&token_id, &token).is_none(),
+            "Token already exists"
+        );
+
+        //call the internal method for adding the token to the owner
+        self.internal_add_token_to_owner(&token.owner_id, &token_id);
+
+        // Construct the mint log as per the events standard.
+        let nft_mint_log: EventLog = EventLog {
+            // Standard name ("nep171").
+            standard: NFT_STANDARD_NAME.to_string(),
+            // Version of the standard ("nft-1.0.0").
+            version: NFT_METADATA_SPEC.to_string(),
+            // The data related with the event stored in a vector.
+            event: EventLogVariant::NftMint(vec![NftMintLog {
+                // Owner of the token.
+                owner_id: token.owner_id.to_string(),
+                // Vector of token IDs that were minted.
+                token_ids: vec![token_id.to_string()],
+                // An optional memo to include.
+                memo: None,
+            }]),
+        };
+
+        // Log the serialized json.
+        env::log_str(&nft_mint_log.to_string());
+
+        let marketplace_id = self.marketplace_contract_id.clone();
+
+        //call the internal method for approving marketplace to transfer token
+        self.internal_approve_token_marketplace(&marketplace_id, &token_id);
+
+        //Lock the token pending marketplace transaction completion
+        assert_eq!(self.tokens_locked.insert(&token_id), true);
+
+        token_id
+    }
+
     //approve pipar marketplace to be able to transfer token
     pub(crate) fn internal_approve_token_marketplace(
         &mut self,
@@ -142,6 +910,12 @@ impl Contract {
 
         //we insert that set for the given account ID.
         self.tokens_per_owner.insert(account_id, &tokens_set);
+
+        //count the tokens of the series held by the account
+        let series_id = self.tokens_by_id.get(token_id).expect("No token").series_id;
+        let key = (series_id, account_id.clone());
+        let held = self.series_holdings.get(&key).unwrap_or(0);
+        self.series_holdings.insert(&key, &(held + 1));
     }
 
     //remove a token from an owner (internal method and can't be called directly via CLI).
@@ -167,6 +941,13 @@ impl Contract {
             //if the token set is not empty, we simply insert it back for the account ID.
             self.tokens_per_owner.insert(account_id, &tokens_set);
         }
+
+        let series_id = self.tokens_by_id.get(token_id).expect("No token").series_id;
+        let key = (series_id, account_id.clone());
+        match self.series_holdings.get(&key).unwrap_or(0) {
+            0 | 1 => self.series_holdings.remove(&key),
+            held => self.series_holdings.insert(&key, &(held - 1)),
+        };
     }
 
     //transfers the NFT to the receiver_id (internal method and can't be called directly via CLI).
@@ -213,6 +994,23 @@ impl Contract {
             "The token owner and the receiver should be different"
         );
 
+        //enforce the anti-scalping rules of the series
+        let series = self.series_by_id.get(&token.series_id).expect("Not a series");
+        if let Some(min_hold_period) = series.resale_rules.as_ref().and_then(|rules| rules.min_hold_period) {
+            let now = env::block_timestamp_ms();
+            let held_until = token.acquired_at.saturating_add(min_hold_period);
+            assert!(
+                now >= held_until,
+                "Token must be held for {} ms before it can be transferred, {} ms left",
+                min_hold_period,
+                held_until - now
+            );
+        }
+        self.internal_assert_holding_limit(token.series_id, &series, receiver_id);
+
+        //a token listed for resale on the store is delisted once it changes hands
+        self.internal_remove_listing(token_id);
+
         //we remove the token from it's current owner's set
         self.internal_remove_token_from_owner(&token.owner_id, token_id);
         //we then add the token to the receiver_id's set
@@ -225,6 +1023,8 @@ impl Contract {
             //reset the approval account IDs
             approved_account_ids: Default::default(),
             next_approval_id: token.next_approval_id,
+            acquired_at: env::block_timestamp_ms(),
+            royalty_at_mint: token.royalty_at_mint,
         };
         //insert that new token into the tokens_by_id, replacing the old entry
         self.tokens_by_id.insert(token_id, &new_token);
@@ -19,6 +19,7 @@ pub use crate::series::*;
 pub use crate::factory::*;
 pub use crate::reward::*;
 pub use crate::affiliate::*;
+pub use crate::addon::*;
 
 mod approval;
 mod enumeration;
@@ -32,18 +33,39 @@ mod series;
 mod factory;
 mod reward;
 mod affiliate;
+mod loyalty;
+mod airdrop;
+mod staking;
+mod referral;
+mod addon;
+mod resale;
+#[cfg(test)]
+mod test_utils;
 
 /// This spec can be treated like a version of the standard.
 pub const NFT_METADATA_SPEC: &str = "1.0.0";
 /// This is the name of the NFT standard we're using
 pub const NFT_STANDARD_NAME: &str = "nep171";
+/// Standard name used for store specific events (affiliates, rewards etc..)
+pub const STORE_STANDARD_NAME: &str = "pipar_store";
+/// Version of the store events
+pub const STORE_EVENT_VERSION: &str = "1.0.0";
 
-// cost of deploying FT token
-pub const TOKEN_BALANCE: u128 = 4_000_000_000_000_000_000_000_000;
+// default margin added to the code storage cost of a deployment, covers the account and its initial state
+pub const DEPLOY_MARGIN: u128 = 500_000_000_000_000_000_000_000;
+
+// 1 near in yocto
+pub const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
 
 // 0.1 near in yocto
 pub const ONE_YOCTO: u128 = 10_000_000_000_000_000_000_000;
 
+// longest hold period (in milliseconds) a series can require before transfers, one year
+pub const MAX_HOLD_PERIOD: u64 = 365 * 24 * 60 * 60 * 1000;
+
+// Series ID used by affiliate requests for the store wide affiliate program
+pub const STORE_AFFILIATE_PROGRAM_ID: SeriesId = 0;
+
 // Attach 0 near token
 pub const NO_DEPOSIT: Balance = 0;
 
@@ -64,6 +86,11 @@ pub struct Series {
     colors: HashMap<String, u32>,
     // Royalty used for all tokens in the collection
     royalty: Option<HashMap<AccountId, u32>>,
+    // Lowest total royalty in basis points any token was minted with, the creation total until the first mint.
+    // The series royalty can't be raised above it
+    max_royalty: u32,
+    // Split of the first sale of each token, the remainder goes to the owner of the collection
+    primary_split: Option<HashMap<AccountId, u32>>,
     // List of affiliates for all the tokens in this series collection
     affiliate: Option<HashMap<AccountId, u32>>,
     // Set of tokens in the collection
@@ -73,10 +100,27 @@ pub struct Series {
     price: Option<Balance>,
     // Owner of the collection
     owner_id: AccountId,
+    // Remaining store tokens that can be rewarded for this series, unlimited if none
+    reward_budget: Option<Balance>,
+    // Sale status, only loyalty members with early access can buy series that aren't live
+    status: SeriesStatus,
+    // Only store token stakers with members access can buy tokens of the series
+    members_only: bool,
+    // Anti-scalping rules applied to resales and transfers of the tokens
+    resale_rules: Option<ResaleRules>,
 }
 
 pub type SeriesId = u64;
 
+// Sale status of a series
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SeriesStatus {
+    Draft,
+    PreSale,
+    Live,
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
@@ -86,15 +130,132 @@ pub struct Contract {
     //marketplace contract id
     pub marketplace_contract_id: AccountId,
 
-    //store token boolean
-    pub token: bool,
+    //store token deployed with deploy_token
+    pub store_token: Option<StoreToken>,
+
+    //state of the last store token deployment, none if it was never deployed
+    pub token_deployment: Option<DeploymentStatus>,
+
+    //royalties overriding the series royalty of single tokens
+    pub token_royalties: LookupMap<TokenId, HashMap<AccountId, u32>>,
 
-    //cost of deploying a token
-    pub token_cost: U128,
+    //limits on the royalties a series can set, tuned by the marketplace
+    pub royalty_limits: RoyaltyLimits,
+
+    //margin added to the code storage cost of a deployment, tuned by the marketplace
+    pub deploy_margin: Balance,
+
+    //add-on contracts deployed by the store
+    pub addons: UnorderedMap<AddonKind, StoreAddon>,
+
+    //versions of the sub-contract code uploaded to the store
+    pub code_versions: UnorderedMap<String, CodeVersion>,
+
+    //sub-contract code of each uploaded version
+    pub code_blobs: LookupMap<String, Vec<u8>>,
 
     //affiliate requests
     pub affiliate_requests: Vector<AffiliatesRequests>,
 
+    //affiliates that were revoked for a series
+    pub revoked_affiliates: LookupSet<(SeriesId, AccountId)>,
+
+    //keeps track of the commissions earned by each affiliate
+    pub affiliate_earnings: LookupMap<AccountId, AffiliateEarnings>,
+
+    //store wide affiliate program
+    pub affiliate_program: LazyOption<AffiliateProgram>,
+
+    //affiliates approved for the store wide affiliate program
+    pub store_affiliates: LookupSet<AccountId>,
+
+    //referral registered by each buyer, used to attribute their purchases to an affiliate
+    pub referrals: LookupMap<AccountId, Referral>,
+
+    //how long (in milliseconds) a registered referral is attributed to the affiliate, forever if none
+    pub attribution_window: Option<u64>,
+
+    //keeps track of the affiliate referral codes
+    pub affiliate_codes: UnorderedMap<String, AffiliateCode>,
+
+    //store policy used to compute the token rewards of each purchase
+    pub reward_policy: LazyOption<RewardPolicy>,
+
+    //remaining store tokens that can be rewarded across the store, unlimited if none
+    pub reward_budget: Option<Balance>,
+
+    //store token rewards accrued by each account until they are claimed
+    pub reward_balances: LookupMap<AccountId, RewardBalance>,
+
+    //how long (in milliseconds) accrued rewards can stay unclaimed, forever if none
+    pub reward_expiry: Option<u64>,
+
+    //store token reward claims persisted until they are delivered
+    pub rewards: UnorderedMap<u64, PendingReward>,
+
+    //rewards that were not delivered yet
+    pub outstanding_rewards: UnorderedSet<u64>,
+
+    //NEP-141 token used as the store reward currency
+    pub reward_token: Option<RewardToken>,
+
+    //accounts already registered on a reward token, keyed by token and account
+    pub reward_token_accounts: LookupSet<(AccountId, AccountId)>,
+
+    //running spend and order count of each buyer on the store
+    pub customer_profiles: LookupMap<AccountId, CustomerProfile>,
+
+    //store defined loyalty tiers, sorted by spend
+    pub loyalty_tiers: LazyOption<Vec<LoyaltyTier>>,
+
+    //airdrops to the holders of a series
+    pub airdrops: UnorderedMap<u64, Airdrop>,
+
+    //holders that already got an airdrop, keyed by airdrop and account
+    pub airdrop_recipients: LookupSet<(u64, AccountId)>,
+
+    //store defined stake tiers, giving perks to store token stakers
+    pub stake_tiers: LazyOption<Vec<StakeTier>>,
+
+    //store token positions staked by each account
+    pub stake_positions: LookupMap<AccountId, Vec<StakePosition>>,
+
+    //ID of the next stake position
+    pub next_stake_position_id: u64,
+
+    //store tokens currently held by the store for stakers
+    pub total_staked: Balance,
+
+    //store tokens held by the store outside of the staked funds
+    pub store_token_treasury: Balance,
+
+    //NEAR deposited by each staker to pay for the storage of their stake positions
+    pub stake_storage: LookupMap<AccountId, Balance>,
+
+    //how long (in milliseconds) unstaked tokens wait before they can be withdrawn, no wait if none
+    pub unstake_cooldown: Option<u64>,
+
+    //store tokens rewarded to buyers who bring other buyers
+    pub buyer_referral_program: LazyOption<BuyerReferralProgram>,
+
+    //referrer of each new buyer
+    pub buyer_referrals: LookupMap<AccountId, BuyerReferral>,
+
+    //number of buyers referred by each customer
+    pub buyer_referral_counts: LookupMap<AccountId, u32>,
+
+    //tokens listed for resale on the store
+    pub listings: UnorderedMap<TokenId, Listing>,
+
+    //keeps track of the listed token IDs of each series
+    pub listings_per_series: LookupMap<SeriesId, UnorderedSet<TokenId>>,
+
+    //number of tokens of each series held by an account
+    pub series_holdings: LookupMap<(SeriesId, AccountId), u32>,
+
+    //keeps track of the sale receipt for a given token ID
+    pub receipts: LookupMap<TokenId, MarketplaceData>,
+
     //approved minters
     pub approved_minters: LookupSet<AccountId>,
 
@@ -131,6 +292,36 @@ pub enum StorageKey {
     TokensById,
     TokensLocked,
     NFTContractMetadata,
+    RevokedAffiliates,
+    AffiliateEarnings,
+    Receipts,
+    AffiliateCodes,
+    AffiliateProgram,
+    StoreAffiliates,
+    Referrals,
+    RewardPolicy,
+    CustomerProfiles,
+    Rewards,
+    OutstandingRewards,
+    RewardTokenAccounts,
+    LoyaltyTiers,
+    RewardBalances,
+    Airdrops,
+    AirdropRecipients,
+    StakeTiers,
+    StakePositions,
+    BuyerReferralProgram,
+    BuyerReferrals,
+    BuyerReferralCounts,
+    CodeVersions,
+    CodeBlobs,
+    Addons,
+    TokenRoyalties,
+    Listings,
+    ListingsPerSeries,
+    ListingsPerSeriesInner { series_id: SeriesId },
+    StakeStorage,
+    SeriesHoldings,
 }
 
 #[near_bindgen]
@@ -193,6 +384,36 @@ impl Contract {
             approved_minters,
             approved_creators,
             affiliate_requests: Vector::new(StorageKey::PendingAffiliates.try_to_vec().unwrap()),
+            revoked_affiliates: LookupSet::new(StorageKey::RevokedAffiliates.try_to_vec().unwrap()),
+            affiliate_earnings: LookupMap::new(StorageKey::AffiliateEarnings.try_to_vec().unwrap()),
+            receipts: LookupMap::new(StorageKey::Receipts.try_to_vec().unwrap()),
+            affiliate_codes: UnorderedMap::new(StorageKey::AffiliateCodes.try_to_vec().unwrap()),
+            affiliate_program: LazyOption::new(StorageKey::AffiliateProgram.try_to_vec().unwrap(), None),
+            store_affiliates: LookupSet::new(StorageKey::StoreAffiliates.try_to_vec().unwrap()),
+            referrals: LookupMap::new(StorageKey::Referrals.try_to_vec().unwrap()),
+            attribution_window: None,
+            reward_policy: LazyOption::new(StorageKey::RewardPolicy.try_to_vec().unwrap(), None),
+            customer_profiles: LookupMap::new(StorageKey::CustomerProfiles.try_to_vec().unwrap()),
+            loyalty_tiers: LazyOption::new(StorageKey::LoyaltyTiers.try_to_vec().unwrap(), None),
+            reward_budget: None,
+            reward_balances: LookupMap::new(StorageKey::RewardBalances.try_to_vec().unwrap()),
+            reward_expiry: None,
+            rewards: UnorderedMap::new(StorageKey::Rewards.try_to_vec().unwrap()),
+            outstanding_rewards: UnorderedSet::new(StorageKey::OutstandingRewards.try_to_vec().unwrap()),
+            reward_token: None,
+            reward_token_accounts: LookupSet::new(StorageKey::RewardTokenAccounts.try_to_vec().unwrap()),
+            airdrops: UnorderedMap::new(StorageKey::Airdrops.try_to_vec().unwrap()),
+            airdrop_recipients: LookupSet::new(StorageKey::AirdropRecipients.try_to_vec().unwrap()),
+            stake_tiers: LazyOption::new(StorageKey::StakeTiers.try_to_vec().unwrap(), None),
+            stake_positions: LookupMap::new(StorageKey::StakePositions.try_to_vec().unwrap()),
+            next_stake_position_id: 1,
+            total_staked: 0,
+            store_token_treasury: 0,
+            stake_storage: LookupMap::new(StorageKey::StakeStorage.try_to_vec().unwrap()),
+            unstake_cooldown: None,
+            buyer_referral_program: LazyOption::new(StorageKey::BuyerReferralProgram.try_to_vec().unwrap(), None),
+            buyer_referrals: LookupMap::new(StorageKey::BuyerReferrals.try_to_vec().unwrap()),
+            buyer_referral_counts: LookupMap::new(StorageKey::BuyerReferralCounts.try_to_vec().unwrap()),
             series_by_id: UnorderedMap::new(StorageKey::SeriesById.try_to_vec().unwrap()),
             //Storage keys are simply the prefixes used for the collections. This helps avoid data collision
             tokens_per_owner: LookupMap::new(StorageKey::TokensPerOwner.try_to_vec().unwrap()),
@@ -202,8 +423,17 @@ impl Contract {
             owner_id,
             //set the &marketplace_id field equal to the passed in marketplace_id.
             marketplace_contract_id,
-            token: false,
-            token_cost: U128::from(TOKEN_BALANCE),
+            store_token: None,
+            token_deployment: None,
+            deploy_margin: DEPLOY_MARGIN,
+            royalty_limits: RoyaltyLimits::default(),
+            token_royalties: LookupMap::new(StorageKey::TokenRoyalties.try_to_vec().unwrap()),
+            listings: UnorderedMap::new(StorageKey::Listings.try_to_vec().unwrap()),
+            listings_per_series: LookupMap::new(StorageKey::ListingsPerSeries.try_to_vec().unwrap()),
+            series_holdings: LookupMap::new(StorageKey::SeriesHoldings.try_to_vec().unwrap()),
+            code_versions: UnorderedMap::new(StorageKey::CodeVersions.try_to_vec().unwrap()),
+            code_blobs: LookupMap::new(StorageKey::CodeBlobs.try_to_vec().unwrap()),
+            addons: UnorderedMap::new(StorageKey::Addons.try_to_vec().unwrap()),
             metadata: LazyOption::new(
                 StorageKey::NFTContractMetadata.try_to_vec().unwrap(),
                 Some(&metadata),
@@ -226,12 +456,14 @@ impl Contract {
         self.owner_id.clone()
     }
 
-    pub fn get_token_cost(&self) -> u128 {
-        self.token_cost.into()
+    /// Get the deposit needed to deploy the store token with an uploaded code version and the given `deploy_token` arguments
+    pub fn get_token_cost(&self, version: String, total_supply: U128, name: String, symbol: String, icon: String) -> U128 {
+        let init_args = ft_init_args(total_supply, name, symbol, icon);
+        U128(self.internal_deploy_cost(&version, &init_args))
     }
 
     pub fn has_token(&self) -> bool {
-        self.token.into()
+        self.store_token.is_some()
     }
 
     /// Ensure that the caller is the owner of the contract
@@ -246,8 +478,8 @@ impl Contract {
 
     /// Ensure that store has not deployed FT token before
     pub fn assert_contract_token_false(&mut self) {
-        assert_eq!(
-            false, self.token,
+        assert!(
+            self.store_token.is_none(),
             "Store owner has already deployed a token"
         )
     }
@@ -283,63 +515,37 @@ impl Contract {
         // Get the series
         let series = self.series_by_id.get(&id.0).expect("Not a series");
 
-        if let Some(affiliateer) = affiliate {
-            // Ensure the passed in affiliate is approved by the owner
-            if let Some(affix) = series.affiliate {
-                assert!(
-                    affix.contains_key(&affiliateer),
-                    "Affiliateer was not approved"
-                );
-                if let Some(percentage) = affix.get(&affiliateer) {
-                        let res = MarketplaceData {
-                            price: price_per_token,
-                            affiliate: true,
-                            affiliate_id: Some(affiliateer.clone()),
-                            affiliate_percentage: Some(percentage.clone()),
-                            token_id,
-                            token_owner: owner_id,
-                            store_owner,
-                        };
-
-                        res
-                } else {
-                    let res = MarketplaceData {
-                        price: price_per_token,
-                        affiliate: false,
-                        affiliate_id: None,
-                        affiliate_percentage: None,
-                        token_id,
-                        token_owner: owner_id,
-                        store_owner,
-                    };
-
-                    res
-                }
-            } else {
-                let res = MarketplaceData {
-                    price: price_per_token,
-                    affiliate: false,
-                    affiliate_id: None,
-                    affiliate_percentage: None,
-                    token_id,
-                    token_owner: owner_id,
-                    store_owner,
-                };
-
-                res
+        // Work out the commission of the passed in affiliate. Panics if the affiliate was not approved
+        let commission = affiliate.and_then(|affiliateer| {
+            self.internal_affiliate_commission(id.0, &series, &affiliateer, price_per_token)
+                .map(|(percentage, commission)| (affiliateer, percentage, commission))
+        });
+
+        let (affiliate_id, affiliate_percentage, affiliate_commission) = match commission {
+            Some((affiliateer, percentage, commission)) => {
+                (Some(affiliateer), Some(percentage), Some(U128(commission)))
             }
-        } else {
-            let res = MarketplaceData {
-                price: price_per_token,
-                affiliate: false,
-                affiliate_id: None,
-                affiliate_percentage: None,
-                token_id,
-                token_owner: owner_id,
-                store_owner,
-            };
-
-            res
+            None => (None, None, None),
+        };
+
+        MarketplaceData {
+            price: price_per_token,
+            affiliate: affiliate_id.is_some(),
+            affiliate_id,
+            affiliate_percentage,
+            affiliate_commission,
+            affiliate_clawed_back: false,
+            affiliate_code: None,
+            affiliate_attributed_at: None,
+            reward_amount: None,
+            reward_issued: false,
+            referrer_id: None,
+            referrer_reward: None,
+            primary_payout: None,
+            settled_by_store: false,
+            token_id,
+            token_owner: owner_id,
+            store_owner,
         }
     }
 
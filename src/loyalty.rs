@@ -0,0 +1,110 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Set the store loyalty tiers, such as Bronze, Silver and Gold. Buyers reach a tier once both their
+    /// spend and order count are above the tier's minimums. Discounts and multipliers are in basis points.
+    pub fn set_loyalty_tiers(&mut self, tiers: Vec<LoyaltyTier>) {
+        self.assert_contract_owner();
+
+        if tiers.is_empty() {
+            self.loyalty_tiers.remove();
+            return;
+        }
+
+        // a 100% discount would make priced series free to mint by anyone
+        assert!(
+            tiers.iter().all(|tier| tier.discount < 10_000),
+            "Loyalty discount must be below 10000"
+        );
+
+        // keep the tiers sorted by spend
+        let mut tiers = tiers;
+        tiers.sort_by_key(|tier| (tier.min_spent.0, tier.min_orders));
+        self.loyalty_tiers.set(&tiers);
+    }
+
+    /// Get the store loyalty tiers
+    pub fn get_loyalty_tiers(&self) -> Vec<LoyaltyTier> {
+        self.loyalty_tiers.get().unwrap_or_default()
+    }
+
+    /// Get the running spend, order count and loyalty tier of a buyer
+    pub fn get_customer_profile(&self, account_id: AccountId) -> JsonCustomerProfile {
+        let profile = self.customer_profiles.get(&account_id);
+        let tier = self.internal_loyalty_tier(&account_id);
+
+        JsonCustomerProfile {
+            orders: profile.as_ref().map_or(0, |p| p.orders),
+            spent: profile.as_ref().map_or(U128(0), |p| p.spent),
+            first_purchase_at: profile.as_ref().map(|p| p.first_purchase_at),
+            last_purchase_at: profile.as_ref().map(|p| p.last_purchase_at),
+            tier,
+            account_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn preview_price_applies_loyalty_discount() {
+        let mut contract = new_contract();
+        let series_id = create_series(&mut contract, None, None, Some(1_000));
+        set_context(OWNER, 0);
+        contract.set_loyalty_tiers(vec![LoyaltyTier {
+            name: "Gold".to_string(),
+            min_spent: U128(100),
+            min_orders: 1,
+            discount: 1_500,
+            reward_multiplier: 10_000,
+            early_access: false,
+        }]);
+
+        assert_eq!(contract.preview_price(U64(series_id), account("buyer.near")), Some(U128(1_000)));
+        contract.internal_record_purchase(&account("buyer.near"), 100);
+        assert_eq!(contract.preview_price(U64(series_id), account("buyer.near")), Some(U128(850)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Loyalty discount must be below 10000")]
+    fn loyalty_discount_below_full_price() {
+        let mut contract = new_contract();
+        contract.set_loyalty_tiers(vec![LoyaltyTier {
+            name: "Gold".to_string(),
+            min_spent: U128(0),
+            min_orders: 0,
+            discount: 10_000,
+            reward_multiplier: 10_000,
+            early_access: false,
+        }]);
+    }
+
+    #[test]
+    fn loyalty_tier() {
+        let tier = |name: &str, min_spent: u128, min_orders: u64| LoyaltyTier {
+            name: name.to_string(),
+            min_spent: U128(min_spent),
+            min_orders,
+            discount: 0,
+            reward_multiplier: 10_000,
+            early_access: false,
+        };
+        let tiers = vec![tier("Bronze", 0, 1), tier("Silver", 100, 2), tier("Gold", 1_000, 5)];
+        let profile = |spent: u128, orders: u64| CustomerProfile {
+            orders,
+            spent: U128(spent),
+            first_purchase_at: 0,
+            last_purchase_at: 0,
+        };
+
+        assert!(profile(0, 0).tier(&tiers).is_none());
+        assert_eq!(profile(500, 1).tier(&tiers).unwrap().name, "Bronze");
+        assert_eq!(profile(500, 2).tier(&tiers).unwrap().name, "Silver");
+        assert_eq!(profile(5_000, 4).tier(&tiers).unwrap().name, "Silver");
+        assert_eq!(profile(5_000, 5).tier(&tiers).unwrap().name, "Gold");
+    }
+}
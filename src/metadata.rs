@@ -7,6 +7,75 @@ pub struct Payout {
     pub payout: HashMap<AccountId, U128>,
 }
 
+impl Payout {
+    //payout of a token sale, the royalty recipients get their share rounded down and the seller gets the rest
+    pub fn from_royalty(
+        royalty: &Option<HashMap<AccountId, u32>>,
+        seller_id: &AccountId,
+        balance: Balance,
+        max_len_payout: u32,
+    ) -> Self {
+        let royalty = royalty.clone().unwrap_or_default();
+
+        //make sure we're not paying out to too many people (GAS limits this)
+        assert!(
+            royalty.len() as u32 <= max_len_payout,
+            "Market cannot payout to that many receivers"
+        );
+
+        Self {
+            payout: split_payout(&royalty, seller_id, balance),
+        }
+    }
+}
+
+// Limits on the royalties a series can set, keeps payouts from underflowing or running out of gas
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoyaltyLimits {
+    pub max_total: u32, // total royalties in basis points
+    pub max_recipients: u32,
+}
+
+impl Default for RoyaltyLimits {
+    fn default() -> Self {
+        Self {
+            max_total: 5_000,
+            max_recipients: 10,
+        }
+    }
+}
+
+impl RoyaltyLimits {
+    //panic naming the offending entry if the royalty is above the limits
+    pub fn assert_valid(&self, royalty: &HashMap<AccountId, u32>) {
+        assert!(
+            royalty.len() as u32 <= self.max_recipients,
+            "Royalty has {} recipients, the maximum is {}",
+            royalty.len(),
+            self.max_recipients
+        );
+
+        // go through the entries in a fixed order so the error is deterministic
+        let mut entries: Vec<(&AccountId, &u32)> = royalty.iter().collect();
+        entries.sort();
+
+        let mut total: u32 = 0;
+        for (account_id, percentage) in entries {
+            assert!(*percentage > 0, "Royalty for {} must be above 0", account_id);
+            total = total.saturating_add(*percentage);
+            assert!(
+                total <= self.max_total,
+                "Royalty of {} for {} brings the total to {}, the maximum is {}",
+                percentage,
+                account_id,
+                total,
+                self.max_total
+            );
+        }
+    }
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct NFTContractMetadata {
@@ -45,6 +114,7 @@ pub struct TokenMetadata {
     pub token_amount_per_unit: U128, // number of tokens to give up by the buyer before accessing this discount
     pub is_reward: bool, // does the seller want to reward a buyer with a it's store tokens after successfully purchasing this product
     pub reward_amount_per_unit: U128, // amount of token to give the buyer after purchasing this product
+    pub category: Option<String>, // category of the product, used for category reward rates
     pub is_custom_user: bool, // is this series collection made for a particular user?
     pub user: Option<String>, // if yes, what is the ID of that user
     pub issued_at: Option<u64>, // When token was issued or minted, Unix epoch in milliseconds
@@ -66,6 +136,10 @@ pub struct Token {
     pub approved_account_ids: HashMap<AccountId, u64>,
     //the next approval ID to give out.
     pub next_approval_id: u64,
+    //when the current owner got the token, Unix epoch in milliseconds
+    pub acquired_at: u64,
+    //total royalty in basis points when the token was minted, its royalty can't be raised above it
+    pub royalty_at_mint: u32,
 }
 
 //The Json token is what will be returned from view calls.
@@ -86,6 +160,8 @@ pub struct JsonToken {
     pub approved_account_ids: HashMap<AccountId, u64>,
     //keep track of the royalty percentages for the token in a hash map
     pub royalty: Option<HashMap<AccountId, u32>>,
+    //split of the first sale of the token
+    pub primary_split: Option<HashMap<AccountId, u32>>,
     //keep track of the price for the token
     pub price: Option<Balance>,
 }
@@ -124,11 +200,339 @@ pub struct MarketplaceData {
     pub affiliate: bool,
     pub affiliate_id: Option<AccountId>,
     pub affiliate_percentage: Option<u32>,
+    pub affiliate_commission: Option<U128>, // commission held by the store for the affiliate
+    pub affiliate_clawed_back: bool, // was the commission clawed back after a refund
+    pub affiliate_code: Option<String>, // referral code used to attribute the sale
+    pub affiliate_attributed_at: Option<u64>, // when the buyer was referred by the affiliate, Unix epoch in milliseconds
+    pub reward_amount: Option<U128>, // store tokens the buyer is rewarded with for this purchase
+    pub reward_issued: bool, // was the reward accrued on the buyer's balance
+    pub referrer_id: Option<AccountId>, // buyer who referred this buyer to the store, only set on the first purchase
+    pub referrer_reward: Option<U128>, // store tokens the referrer is rewarded with for this purchase
+    pub primary_payout: Option<HashMap<AccountId, U128>>, // primary sale split paid out by the store at mint
+    pub settled_by_store: bool, // was the sale paid out by the store, the marketplace must not pay it out again
     pub token_id: String,
     pub token_owner: AccountId,
     pub store_owner: AccountId,
 }
 
+// Referral code registered by an approved affiliate
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AffiliateCode {
+    pub code: String, // unique lowercase code shared by the affiliate
+    pub account_id: AccountId, // affiliate the code resolves to
+    pub series_id: Option<U64>, // series the code is valid for, the whole store if none
+    pub active: bool, // deactivated codes are refused at mint time
+}
+
+// Referral registered on-chain by a buyer following an affiliate's link
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Referral {
+    pub affiliate_id: AccountId, // affiliate the buyer was referred by
+    pub affiliate_code: Option<String>, // referral code the buyer followed
+    pub registered_at: u64, // when the referral was registered, Unix epoch in milliseconds
+}
+
+// Referral of a new buyer by an existing customer of the store
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BuyerReferral {
+    pub referrer_id: AccountId, // customer who referred the buyer
+    pub registered_at: u64, // Unix epoch in milliseconds
+    pub rewarded: bool, // were both parties rewarded on the buyer's first purchase
+}
+
+// Store tokens rewarded to buyers who bring other buyers
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BuyerReferralProgram {
+    pub referrer_reward: U128, // tokens for the referrer on the referred buyer's first purchase
+    pub referee_reward: U128, // tokens added to the reward of the referred buyer's first purchase
+    pub max_referrals: u32, // number of buyers a single customer can refer
+}
+
+// Earnings of an affiliate for a single series
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AffiliateSeriesEarnings {
+    pub sales: u64, // number of sales attributed to the affiliate
+    pub revenue: U128, // total price of the attributed sales
+    pub accrued: U128, // commission accrued on the attributed sales
+    pub clawed_back: U128, // commission clawed back after refunds
+}
+
+impl Default for AffiliateSeriesEarnings {
+    fn default() -> Self {
+        Self {
+            sales: 0,
+            revenue: U128(0),
+            accrued: U128(0),
+            clawed_back: U128(0),
+        }
+    }
+}
+
+// Commission ledger of an affiliate across all series
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct AffiliateEarnings {
+    pub accrued: Balance, // total commission accrued
+    pub paid: Balance, // total commission withdrawn by the affiliate
+    pub clawed_back: Balance, // total commission clawed back after refunds
+    pub series: HashMap<SeriesId, AffiliateSeriesEarnings>, // breakdown of the earnings per series
+    pub period_started_at: u64, // start of the current commission cap period, Unix epoch in milliseconds
+    pub period_commission: Balance, // commission accrued during the current cap period
+    pub clicks: u64, // referrals registered on-chain for the affiliate
+    pub refunds: u64, // attributed sales that were refunded
+}
+
+impl AffiliateEarnings {
+    // commission that can still be withdrawn by the affiliate
+    pub fn available(&self) -> Balance {
+        self.accrued.saturating_sub(self.paid + self.clawed_back)
+    }
+
+    // total price of the sales brought in by the affiliate across all series
+    pub fn volume(&self) -> Balance {
+        self.series.values().map(|s| s.revenue.0).sum()
+    }
+
+    // number of sales attributed to the affiliate across all series
+    pub fn conversions(&self) -> u64 {
+        self.series.values().map(|s| s.sales).sum()
+    }
+
+    // commission accrued during the cap period running at `now`
+    pub fn commission_in_period(&self, now: u64, period_duration: u64) -> Balance {
+        if now >= self.period_started_at + period_duration {
+            0
+        } else {
+            self.period_commission
+        }
+    }
+}
+
+//Performance of an affiliate returned from view calls.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AffiliateStats {
+    pub account_id: AccountId,
+    pub clicks: u64, // referrals registered on-chain
+    pub conversions: u64, // attributed sales
+    pub revenue: U128, // total price of the attributed sales
+    pub commission: U128, // commission accrued on the attributed sales
+    pub refunds: u64, // attributed sales that were refunded
+    pub refund_rate: u32, // refunds over conversions in basis points
+}
+
+// Commission percentage (in basis points) given once an affiliate brought in `min_volume` yoctoNEAR of sales
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CommissionTier {
+    pub min_volume: U128,
+    pub percentage: u32,
+}
+
+// Store wide affiliate program, affiliates approved for it can promote every series of the store
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AffiliateProgram {
+    pub tiers: Vec<CommissionTier>, // default commission tiers based on the volume brought in
+    pub series_overrides: HashMap<SeriesId, u32>, // commission percentage (in basis points) for specific series
+    pub max_commission_per_order: Option<U128>, // maximum commission paid on a single order
+    pub max_commission_per_period: Option<U128>, // maximum commission accrued by an affiliate per period
+    pub period_duration: u64, // length of a cap period in milliseconds
+}
+
+impl AffiliateProgram {
+    // commission percentage for a series given the volume already brought in by the affiliate
+    pub fn percentage(&self, series_id: SeriesId, volume: Balance) -> u32 {
+        if let Some(percentage) = self.series_overrides.get(&series_id) {
+            return *percentage;
+        }
+
+        // tiers are kept sorted by volume, so the last matching tier is the highest one reached
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| tier.min_volume.0 <= volume)
+            .map_or(0, |tier| tier.percentage)
+    }
+
+    // cap a commission to the per order limit and what's left of the per period limit
+    pub fn cap(&self, commission: Balance, period_commission: Balance) -> Balance {
+        let mut commission = commission;
+        if let Some(max) = self.max_commission_per_order {
+            commission = commission.min(max.0);
+        }
+        if let Some(max) = self.max_commission_per_period {
+            commission = commission.min(max.0.saturating_sub(period_commission));
+        }
+        commission
+    }
+}
+
+//The Json affiliate earnings is what will be returned from view calls.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JsonAffiliateEarnings {
+    pub account_id: AccountId,
+    pub accrued: U128,
+    pub paid: U128,
+    pub clawed_back: U128,
+    pub available: U128,
+}
+
+// Running spend and order count of a buyer across all series
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CustomerProfile {
+    pub orders: u64, // number of completed orders
+    pub spent: U128, // total NEAR spent on the store
+    pub first_purchase_at: u64, // Unix epoch in milliseconds
+    pub last_purchase_at: u64, // Unix epoch in milliseconds
+}
+
+impl CustomerProfile {
+    // highest loyalty tier reached by the buyer, tiers must be sorted by spend
+    pub fn tier<'a>(&self, tiers: &'a [LoyaltyTier]) -> Option<&'a LoyaltyTier> {
+        tiers
+            .iter()
+            .rev()
+            .find(|tier| self.spent.0 >= tier.min_spent.0 && self.orders >= tier.min_orders)
+    }
+}
+
+// Store defined loyalty tier such as Bronze, Silver or Gold
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LoyaltyTier {
+    pub name: String,
+    pub min_spent: U128, // NEAR spent to reach the tier
+    pub min_orders: u64, // orders completed to reach the tier
+    pub discount: u32, // discount on every purchase in basis points
+    pub reward_multiplier: u32, // multiplier applied to rewards in basis points, 10000 leaves them unchanged
+    pub early_access: bool, // can buy series in a draft or pre-sale state
+}
+
+//The Json customer profile is what will be returned from view calls.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JsonCustomerProfile {
+    pub account_id: AccountId,
+    pub orders: u64,
+    pub spent: U128,
+    pub first_purchase_at: Option<u64>,
+    pub last_purchase_at: Option<u64>,
+    pub tier: Option<LoyaltyTier>,
+}
+
+// Delivery status of a store token reward
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RewardStatus {
+    Pending, // transfer in flight
+    Delivered, // tokens received by the buyer
+    Failed, // transfer failed, can be retried
+}
+
+// NEP-148 metadata returned by `ft_metadata`
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FungibleTokenMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub icon: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<Base64VecU8>,
+    pub decimals: u8,
+}
+
+// NEP-141 token used as the store reward currency
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardToken {
+    pub account_id: AccountId,
+    pub metadata: FungibleTokenMetadata,
+}
+
+// Store token rewards accrued by an account until they are claimed
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct RewardBalance {
+    pub amount: U128,
+    pub last_accrued_at: u64, // Unix epoch in milliseconds
+}
+
+//The Json reward balance is what will be returned from view calls.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JsonRewardBalance {
+    pub account_id: AccountId,
+    pub amount: U128,
+    pub expires_at: Option<u64>, // when the unclaimed rewards expire, Unix epoch in milliseconds
+}
+
+// Store token reward claim persisted until it is delivered
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingReward {
+    pub reward_id: u64,
+    pub receiver_id: AccountId, // account rewarded
+    pub amount: U128, // amount of store tokens
+    pub status: RewardStatus,
+    pub attempts: u32, // number of transfers attempted
+    pub created_at: u64, // Unix epoch in milliseconds
+}
+
+// Time limited multiplier (in basis points) applied to the rewards of purchases made between `starts_at` and `ends_at`
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardMultiplier {
+    pub multiplier: u32, // 20000 doubles the reward
+    pub starts_at: u64, // Unix epoch in milliseconds
+    pub ends_at: u64, // Unix epoch in milliseconds
+}
+
+// Store policy used to compute the store token reward of a purchase
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardPolicy {
+    pub per_near_spent: U128, // tokens rewarded for each NEAR spent
+    pub first_purchase_bonus: U128, // tokens added to the first purchase of a buyer on the store
+    pub category_rates: HashMap<String, U128>, // tokens per unit for products of a category, replaces `reward_amount_per_unit`
+    pub multipliers: Vec<RewardMultiplier>, // time limited multipliers
+}
+
+impl RewardPolicy {
+    // compute the reward of a purchase given the per unit reward of the series
+    pub fn reward(
+        &self,
+        reward_per_unit: Balance,
+        category: Option<&String>,
+        price: Balance,
+        first_purchase: bool,
+        now: u64,
+    ) -> Balance {
+        let per_unit = category
+            .and_then(|category| self.category_rates.get(category))
+            .map_or(reward_per_unit, |rate| rate.0);
+
+        let mut reward = per_unit + price * self.per_near_spent.0 / ONE_NEAR;
+        if first_purchase {
+            reward += self.first_purchase_bonus.0;
+        }
+
+        for multiplier in self.multipliers.iter() {
+            if multiplier.starts_at <= now && now < multiplier.ends_at {
+                reward = reward * multiplier.multiplier as u128 / 10_000;
+            }
+        }
+
+        reward
+    }
+}
+
 pub trait NonFungibleTokenMetadata {
     //view call for returning the contract metadata
     fn nft_metadata(&self) -> NFTContractMetadata;
@@ -140,3 +544,96 @@ impl NonFungibleTokenMetadata for Contract {
         self.metadata.get().unwrap()
     }
 }
+
+// Perks of staking the store token, such as a discount or access to members-only series
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeTier {
+    pub name: String,
+    pub min_amount: U128, // store tokens staked in a single position to reach the tier
+    pub min_lock_duration: u64, // lock period of the position in milliseconds to reach the tier
+    pub discount: u32, // discount on every purchase in basis points
+    pub members_access: bool, // can buy members-only series
+}
+
+// Store tokens staked by an account
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakePosition {
+    pub position_id: u64,
+    pub amount: U128,
+    pub lock_duration: u64, // milliseconds the tokens can't be unstaked for
+    pub staked_at: u64, // Unix epoch in milliseconds
+    pub unstaked_at: Option<u64>, // start of the cooldown, perks stop once unstaked
+}
+
+impl StakePosition {
+    //check if the position still counts towards stake perks
+    pub fn is_active(&self) -> bool {
+        self.unstaked_at.is_none()
+    }
+
+    //check if the position reaches a stake tier
+    pub fn reaches(&self, tier: &StakeTier) -> bool {
+        self.is_active() && self.amount.0 >= tier.min_amount.0 && self.lock_duration >= tier.min_lock_duration
+    }
+}
+
+// Arguments passed in the `msg` of `ft_transfer_call` when staking
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeArgs {
+    pub lock_duration: u64,
+}
+
+// Anti-scalping rules applied to the resales and transfers of a series
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ResaleRules {
+    pub max_resale_price: Option<u32>, // maximum resale price relative to the series price in basis points, 20000 is 2x
+    pub min_hold_period: Option<u64>, // milliseconds a token must be held before it can be transferred
+    pub max_per_account: Option<u32>, // tokens of the series a single account can hold
+}
+
+impl ResaleRules {
+    //get the highest price a token of a series with the given price can be resold for
+    pub fn max_price(&self, series_price: Option<Balance>) -> Option<Balance> {
+        let multiplier = self.max_resale_price?;
+        let price = series_price?;
+        Some(price * multiplier as u128 / 10_000u128)
+    }
+}
+
+// Token listed for resale on the store by its owner
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Listing {
+    pub token_id: TokenId,
+    pub series_id: U64,
+    pub owner_id: AccountId, // owner of the token when it was listed
+    pub price: U128, // price in yoctoNEAR
+    pub listed_at: u64, // Unix epoch in milliseconds
+}
+
+// What each holder of a series gets from an airdrop
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AirdropKind {
+    // store tokens accrued on the holder's reward balance
+    Reward { amount: U128 },
+    // free token minted from another series
+    Mint { series_id: U64, color: String },
+}
+
+// Airdrop to the holders of a series, processed in batches of tokens
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Airdrop {
+    pub airdrop_id: u64,
+    pub series_id: U64, // series whose holders get the airdrop
+    pub kind: AirdropKind,
+    pub cursor: u64, // index of the next token of the series to process
+    pub recipients: u64, // number of holders that got the airdrop
+    pub completed: bool,
+    pub created_at: u64, // Unix epoch in milliseconds
+}
@@ -173,6 +173,8 @@ impl NonFungibleTokenCore for Contract {
                 .series_by_id
                 .get(&token.series_id)
                 .expect("Not a series");
+            // Get the royalty of the token, its own override or the series royalty
+            let royalty = self.internal_royalty_for(&token_id, &cur_series);
             // Get the metadata for the series
             let mut metadata = cur_series.metadata;
 
@@ -196,7 +198,8 @@ impl NonFungibleTokenCore for Contract {
                 colors: cur_series.colors,
                 price: cur_series.price,
                 approved_account_ids: token.approved_account_ids,
-                royalty: cur_series.royalty,
+                royalty,
+                primary_split: cur_series.primary_split,
             })
         } else {
             //if there wasn't a token ID in the tokens_by_id collection, we return None
@@ -0,0 +1,152 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Set the store tokens rewarded to customers who bring new buyers and to the buyers they bring.
+    /// Passing in none ends the program.
+    pub fn set_buyer_referral_program(&mut self, program: Option<BuyerReferralProgram>) {
+        self.assert_contract_owner();
+
+        match program {
+            Some(program) => {
+                self.buyer_referral_program.set(&program);
+            }
+            None => {
+                self.buyer_referral_program.remove();
+            }
+        }
+    }
+
+    /// Get the buyer referral program of the store
+    pub fn get_buyer_referral_program(&self) -> Option<BuyerReferralProgram> {
+        self.buyer_referral_program.get()
+    }
+
+    /// Register the customer who referred the caller to the store. The caller must not have bought anything yet
+    /// and can only be referred once, the referrer must have completed an order. Both get a store token reward
+    /// on the caller's first purchase. Caller must attach enough $NEAR to cover storage.
+    #[payable]
+    pub fn register_buyer_referral(&mut self, referrer_id: AccountId) {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let program = self.buyer_referral_program.get().expect("Store has no buyer referral program");
+        let buyer_id = env::predecessor_account_id();
+
+        require!(buyer_id != referrer_id, "Cannot refer yourself");
+        require!(
+            self.customer_profiles.get(&buyer_id).is_none_or(|profile| profile.orders == 0),
+            "Only new buyers can be referred"
+        );
+        require!(
+            self.buyer_referrals.get(&buyer_id).is_none(),
+            "Buyer was already referred"
+        );
+        require!(
+            self.customer_profiles.get(&referrer_id).is_some_and(|profile| profile.orders > 0),
+            "Referrer must have completed an order"
+        );
+
+        let referrals = self.buyer_referral_counts.get(&referrer_id).unwrap_or(0);
+        require!(
+            referrals < program.max_referrals,
+            "Referrer has reached the maximum number of referrals"
+        );
+
+        self.buyer_referral_counts.insert(&referrer_id, &(referrals + 1));
+        self.buyer_referrals.insert(
+            &buyer_id,
+            &BuyerReferral {
+                referrer_id,
+                registered_at: env::block_timestamp_ms(),
+                rewarded: false,
+            },
+        );
+
+        //calculate the required storage which was the used - initial
+        let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
+
+        //refund any excess storage if the user attached too much. Panic if they didn't attach enough to cover the required.
+        refund_deposit(required_storage_in_bytes);
+    }
+
+    /// Get the customer who referred a buyer
+    pub fn get_buyer_referral(&self, account_id: AccountId) -> Option<BuyerReferral> {
+        self.buyer_referrals.get(&account_id)
+    }
+
+    /// Get the number of buyers referred by a customer
+    pub fn get_buyer_referral_count(&self, account_id: AccountId) -> u32 {
+        self.buyer_referral_counts.get(&account_id).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    //store rewarding 20 tokens to referrers and 5 to referees, where alice.near and carol.near completed an order
+    fn referral_store(max_referrals: u32) -> Contract {
+        let mut contract = new_contract();
+        set_context(OWNER, 0);
+        contract.set_buyer_referral_program(Some(BuyerReferralProgram {
+            referrer_reward: U128(20),
+            referee_reward: U128(5),
+            max_referrals,
+        }));
+        contract.internal_record_purchase(&account("alice.near"), ONE_NEAR);
+        contract.internal_record_purchase(&account("carol.near"), ONE_NEAR);
+        contract
+    }
+
+    fn refer(contract: &mut Contract, buyer_id: &str, referrer_id: &str) {
+        set_context(buyer_id, ONE_NEAR);
+        contract.register_buyer_referral(account(referrer_id));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot refer yourself")]
+    fn referral_refuses_self_referral() {
+        let mut contract = referral_store(5);
+        refer(&mut contract, "alice.near", "alice.near");
+    }
+
+    #[test]
+    #[should_panic(expected = "Buyer was already referred")]
+    fn referral_once_per_buyer() {
+        let mut contract = referral_store(5);
+        refer(&mut contract, "bob.near", "alice.near");
+        refer(&mut contract, "bob.near", "carol.near");
+    }
+
+    #[test]
+    #[should_panic(expected = "Referrer has reached the maximum number of referrals")]
+    fn referral_capped_per_referrer() {
+        let mut contract = referral_store(1);
+        refer(&mut contract, "bob.near", "alice.near");
+        assert_eq!(contract.get_buyer_referral_count(account("alice.near")), 1);
+        refer(&mut contract, "dave.near", "alice.near");
+    }
+
+    #[test]
+    fn referral_rewards_the_first_purchase() {
+        let mut contract = referral_store(5);
+        refer(&mut contract, "bob.near", "alice.near");
+        let series_id = create_series(&mut contract, None, None, Some(ONE_NEAR));
+
+        set_context(MARKETPLACE, 0);
+        contract.nft_mint(U64(series_id), account("bob.near"), U128(2 * ONE_NEAR), "red".to_string(), None, None);
+        let receipt = contract.get_receipt(format!("{}:1", series_id)).unwrap();
+        assert_eq!(receipt.referrer_id, Some(account("alice.near")));
+        assert_eq!(receipt.referrer_reward, Some(U128(20)));
+        assert_eq!(receipt.reward_amount, Some(U128(5)));
+        assert!(contract.get_buyer_referral(account("bob.near")).unwrap().rewarded);
+
+        //later purchases don't reward the referral again
+        contract.nft_mint(U64(series_id), account("bob.near"), U128(2 * ONE_NEAR), "red".to_string(), None, None);
+        let receipt = contract.get_receipt(format!("{}:2", series_id)).unwrap();
+        assert!(receipt.referrer_id.is_none());
+        assert!(receipt.reward_amount.is_none());
+    }
+}
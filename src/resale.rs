@@ -0,0 +1,245 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// List a token for resale on the store at a price in yoctoNEAR. Can only be called by the token owner.
+    /// Caller must attach enough $NEAR to cover storage.
+    #[payable]
+    pub fn list_token(&mut self, token_id: TokenId, price: U128) {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let token = self.tokens_by_id.get(&token_id).expect("No token");
+        let owner_id = env::predecessor_account_id();
+        assert_eq!(token.owner_id, owner_id, "Predecessor must be the token owner.");
+        require!(price.0 > 0, "Price must be above 0");
+        require!(self.listings.get(&token_id).is_none(), "Token is already listed");
+        let series = self.series_by_id.get(&token.series_id).expect("Not a series");
+        self.internal_assert_resale_price(&series, price.0);
+
+        self.listings.insert(
+            &token_id,
+            &Listing {
+                token_id: token_id.clone(),
+                series_id: U64(token.series_id),
+                owner_id,
+                price,
+                listed_at: env::block_timestamp_ms(),
+            },
+        );
+
+        //add the token to the listings of its series
+        let mut listings_set = self.listings_per_series.get(&token.series_id).unwrap_or_else(|| {
+            UnorderedSet::new(
+                StorageKey::ListingsPerSeriesInner {
+                    series_id: token.series_id,
+                }
+                .try_to_vec()
+                .unwrap(),
+            )
+        });
+        listings_set.insert(&token_id);
+        self.listings_per_series.insert(&token.series_id, &listings_set);
+
+        //calculate the required storage which was the used - initial
+        let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
+
+        //refund any excess storage if the user attached too much. Panic if they didn't attach enough to cover the required.
+        refund_deposit(required_storage_in_bytes);
+    }
+
+    /// Change the price of a listed token. Can only be called by the token owner.
+    pub fn update_listing_price(&mut self, token_id: TokenId, price: U128) {
+        let mut listing = self.listings.get(&token_id).expect("Token is not listed");
+        assert_eq!(listing.owner_id, env::predecessor_account_id(), "Predecessor must be the token owner.");
+        require!(price.0 > 0, "Price must be above 0");
+        let series = self.series_by_id.get(&listing.series_id.0).expect("Not a series");
+        self.internal_assert_resale_price(&series, price.0);
+
+        listing.price = price;
+        self.listings.insert(&token_id, &listing);
+    }
+
+    /// Remove a token from resale. Can only be called by the token owner, the storage deposit is refunded.
+    pub fn cancel_listing(&mut self, token_id: TokenId) {
+        let listing = self.listings.get(&token_id).expect("Token is not listed");
+        assert_eq!(listing.owner_id, env::predecessor_account_id(), "Predecessor must be the token owner.");
+
+        self.internal_remove_listing(&token_id);
+    }
+
+    /// Buy a listed token by attaching at least its price. The sale is paid out with the token royalty
+    /// like `nft_payout`, with the remainder going to the seller. Any excess deposit is refunded.
+    /// `max_len_payout` caps the number of accounts paid out, as in `nft_transfer_payout`.
+    #[payable]
+    pub fn buy_listing(&mut self, token_id: TokenId, max_len_payout: u32) {
+        let listing = self.listings.get(&token_id).expect("Token is not listed");
+        let buyer_id = env::predecessor_account_id();
+        require!(buyer_id != listing.owner_id, "Cannot buy your own listing");
+        //Confirm tokenId is not locked
+        assert!(
+            !self.tokens_locked.contains(&token_id),
+            "Token is currently locked, try again later"
+        );
+
+        let attached_deposit = env::attached_deposit();
+        assert!(
+            attached_deposit >= listing.price.0,
+            "Must attach at least the listing price of {} yoctoNEAR",
+            listing.price.0
+        );
+
+        //work out the payout the same way as `nft_payout`, the token royalty with the remainder going to the seller
+        //the series rules may have changed since the token was listed
+        let series = self.series_by_id.get(&listing.series_id.0).expect("Not a series");
+        self.internal_assert_resale_price(&series, listing.price.0);
+        let royalty = self.internal_royalty_for(&token_id, &series);
+        let payout = Payout::from_royalty(&royalty, &listing.owner_id, listing.price.0, max_len_payout);
+
+        //transfer the token to the buyer, which also removes the listing
+        let previous_token = self.internal_transfer(
+            &listing.owner_id,
+            &buyer_id,
+            &token_id,
+            None,
+            Some("Store resale".to_string()),
+        );
+
+        //refund the previous token owner for the storage used up by the previous approved account IDs
+        refund_approved_account_ids(
+            previous_token.owner_id.clone(),
+            &previous_token.approved_account_ids,
+        );
+
+        //pay out the seller and the royalty recipients
+        for (account_id, amount) in payout.payout.into_iter() {
+            if amount.0 > 0 {
+                Promise::new(account_id).transfer(amount.0);
+            }
+        }
+
+        //refund what was attached above the price
+        let refund = attached_deposit - listing.price.0;
+        if refund > 0 {
+            Promise::new(buyer_id).transfer(refund);
+        }
+    }
+
+    /// Get the resale listing of a token
+    pub fn get_listing(&self, token_id: TokenId) -> Option<Listing> {
+        self.listings.get(&token_id)
+    }
+
+    /// Get the number of tokens of a series listed for resale
+    pub fn get_listings_supply_for_series(&self, id: U64) -> U128 {
+        self.listings_per_series
+            .get(&id.0)
+            .map_or(U128(0), |listings_set| U128(listings_set.len() as u128))
+    }
+
+    /// Paginate through the resale listings of a series
+    pub fn get_listings_for_series(
+        &self,
+        id: U64,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Listing> {
+        let listings_set = if let Some(listings_set) = self.listings_per_series.get(&id.0) {
+            listings_set
+        } else {
+            return vec![];
+        };
+
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        listings_set
+            .iter()
+            //skip to the index we specified in the start variable
+            .skip(start as usize)
+            //take the first "limit" elements in the vector. If we didn't specify a limit, use 50
+            .take(limit.unwrap_or(50) as usize)
+            //we'll map the token IDs into their listings
+            .map(|token_id| self.listings.get(&token_id).unwrap())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    //mint a token of a series with a 10% royalty to the seller, unlock it and list it for 100 NEAR
+    fn listed_token(contract: &mut Contract) -> TokenId {
+        let royalty = HashMap::from([(account("creator.near"), 1_000)]);
+        let series_id = create_series(contract, Some(royalty), None, None);
+        let token_id = mint(contract, series_id, "seller.near");
+        contract.unlock_token(&token_id);
+
+        set_context("seller.near", ONE_NEAR);
+        contract.list_token(token_id.clone(), U128(100 * ONE_NEAR));
+        token_id
+    }
+
+    #[test]
+    fn buy_listing_transfers_and_delists() {
+        let mut contract = new_contract();
+        let token_id = listed_token(&mut contract);
+
+        set_context("buyer.near", 100 * ONE_NEAR);
+        contract.buy_listing(token_id.clone(), 2);
+
+        let token = contract.tokens_by_id.get(&token_id).unwrap();
+        assert_eq!(token.owner_id, account("buyer.near"));
+        assert!(contract.get_listing(token_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Token is currently locked, try again later")]
+    fn buy_listing_refuses_locked_tokens() {
+        let mut contract = new_contract();
+        let series_id = create_series(&mut contract, None, None, None);
+        let token_id = mint(&mut contract, series_id, "seller.near");
+
+        set_context("seller.near", ONE_NEAR);
+        contract.list_token(token_id.clone(), U128(100 * ONE_NEAR));
+
+        set_context("buyer.near", 100 * ONE_NEAR);
+        contract.buy_listing(token_id, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Market cannot payout to that many receivers")]
+    fn buy_listing_honours_max_len_payout() {
+        let mut contract = new_contract();
+        let token_id = listed_token(&mut contract);
+
+        set_context("buyer.near", 100 * ONE_NEAR);
+        contract.buy_listing(token_id, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Must attach at least the listing price")]
+    fn buy_listing_needs_the_price() {
+        let mut contract = new_contract();
+        let token_id = listed_token(&mut contract);
+
+        set_context("buyer.near", ONE_NEAR);
+        contract.buy_listing(token_id, 2);
+    }
+
+    #[test]
+    fn resale_rules_max_price() {
+        let rules = ResaleRules {
+            max_resale_price: Some(15_000),
+            min_hold_period: None,
+            max_per_account: None,
+        };
+        assert_eq!(rules.max_price(Some(1_000)), Some(1_500));
+        assert_eq!(rules.max_price(None), None);
+
+        let rules = ResaleRules { max_resale_price: None, ..rules };
+        assert_eq!(rules.max_price(Some(1_000)), None);
+    }
+}
@@ -1,72 +1,461 @@
 use crate::*;
-// use near_sdk::ext_contract;
+use near_sdk::PromiseResult;
 
 #[near_bindgen]
 impl Contract {
+    /// Reward the buyer of a token, and the customer who referred them, with the store tokens recorded on the token's receipt.
+    /// Tokens without a receipt, such as free mints, get the flat `reward_amount_per_unit` of the series once,
+    /// recorded on a receipt of their own.
+    /// The reward is accrued on the balances, which is then collected at once with `claim_rewards`.
     pub fn reward_with_token(
         &mut self,
-        id: U64, receiver_id: AccountId,
-    ) -> Promise {
+        id: U64, receiver_id: AccountId, token_id: Option<TokenId>,
+    ) -> U128 {
         //ensure smart contract is only called by pipar marketplace
         self.assert_marketplace_contract();
-        //confirm seller has a token deployed
-        assert_eq!(
-            true, self.token,
-            "Store owner has not deployed a token yet"
-        );
+        //confirm store has a reward token
+        assert!(self.reward_token.is_some(), "Store has no reward token yet");
 
         // Get the series and how many tokens currently exist (edition number = cur_len + 1)
+        let series = self.series_by_id.get(&id.0).expect("Not a series");
+
+        //rewards are issued per token so that they can only be issued once
+        let token_id = token_id.expect("Token ID is required");
+        require!(
+            self.tokens_by_id.get(&token_id).map(|token| token.series_id) == Some(id.0),
+            "Token is not part of the series"
+        );
+
+        // Get the reward computed with the store's reward policy when the token was bought
+        let mut receipt = match self.receipts.get(&token_id) {
+            Some(receipt) => receipt,
+            None => {
+                assert!(series.metadata.is_reward, "There's no token reward for this product");
+                //rewards stop once the series or store budget runs out
+                let token_quantity = series
+                    .metadata
+                    .reward_amount_per_unit
+                    .0
+                    .min(series.reward_budget.unwrap_or(Balance::MAX))
+                    .min(self.reward_budget.unwrap_or(Balance::MAX));
+                if token_quantity > 0 {
+                    self.internal_use_reward_budget(id.0, token_quantity);
+                    self.internal_accrue_reward(&receiver_id, token_quantity);
+                }
+
+                //record the issued reward so that it can't be issued again
+                self.receipts.insert(&token_id, &MarketplaceData {
+                    price: 0,
+                    affiliate: false,
+                    affiliate_id: None,
+                    affiliate_percentage: None,
+                    affiliate_commission: None,
+                    affiliate_clawed_back: false,
+                    affiliate_code: None,
+                    affiliate_attributed_at: None,
+                    reward_amount: Some(U128(token_quantity)),
+                    reward_issued: true,
+                    referrer_id: None,
+                    referrer_reward: None,
+                    primary_payout: None,
+                    settled_by_store: false,
+                    token_id: token_id.clone(),
+                    token_owner: receiver_id,
+                    store_owner: self.owner_id.clone(),
+                });
+                return U128(token_quantity);
+            }
+        };
+
+        // Buyer referral rewards are issued whether or not the product has a token reward
+        assert!(
+            series.metadata.is_reward || receipt.referrer_reward.is_some(),
+            "There's no token reward for this product"
+        );
+        require!(!receipt.reward_issued, "Token reward was already issued");
+        require!(
+            receipt.reward_amount.is_some() || receipt.referrer_reward.is_some(),
+            "There's no token reward for this purchase"
+        );
+        let token_quantity = receipt.reward_amount.unwrap_or(U128(0));
+
+        if token_quantity.0 > 0 {
+            self.internal_accrue_reward(&receiver_id, token_quantity.0);
+        }
+
+        // The customer who referred the buyer gets their reward on the buyer's first purchase
+        if let (Some(referrer_id), Some(referrer_reward)) = (&receipt.referrer_id, receipt.referrer_reward) {
+            if referrer_reward.0 > 0 {
+                self.internal_accrue_reward(referrer_id, referrer_reward.0);
+            }
+        }
+
+        receipt.reward_issued = true;
+        self.receipts.insert(&receipt.token_id, &receipt);
+
+        token_quantity
+    }
+
+    /// Claim all the store token rewards accrued by the caller with a single transfer.
+    /// The claim is persisted so that it can be retried if the transfer fails.
+    /// An expired balance is returned to the store reward budget instead and nothing is claimed.
+    pub fn claim_rewards(&mut self) -> PromiseOrValue<Option<PendingReward>> {
+        let account_id = env::predecessor_account_id();
+        let balance = self.reward_balances.get(&account_id).expect("No rewards to claim");
+
+        if self.internal_reward_balance_expired(&balance) {
+            self.internal_expire_reward_balance(&account_id);
+            return PromiseOrValue::Value(None);
+        }
+
+        self.reward_balances.remove(&account_id);
+        let reward_id = self.internal_add_reward(account_id, balance.amount.0);
+
+        PromiseOrValue::Promise(self.internal_send_reward(reward_id))
+    }
+
+    /// Return expired reward balances of the passed in accounts to the store reward budget. Can be called by anyone
+    pub fn expire_rewards(&mut self, account_ids: Vec<AccountId>) -> U128 {
+        let expired = account_ids
+            .iter()
+            .map(|account_id| self.internal_expire_reward_balance(account_id))
+            .sum();
+
+        U128(expired)
+    }
+
+    /// Set how long (in milliseconds) accrued rewards can stay unclaimed before they expire. Passing in none disables expiry
+    pub fn set_reward_expiry(&mut self, reward_expiry: Option<u64>) {
+        self.assert_contract_owner();
+        self.reward_expiry = reward_expiry;
+    }
+
+    /// Get the store token rewards accrued by an account
+    pub fn get_reward_balance(&self, account_id: AccountId) -> JsonRewardBalance {
+        let balance = self.reward_balances.get(&account_id);
+
+        JsonRewardBalance {
+            amount: balance.as_ref().map_or(U128(0), |b| b.amount),
+            expires_at: balance
+                .as_ref()
+                .and_then(|b| self.reward_expiry.map(|expiry| b.last_accrued_at + expiry)),
+            account_id,
+        }
+    }
+
+    /// Retry a reward whose transfer failed. Can be called by the rewarded buyer, the store owner or the marketplace
+    pub fn retry_reward(&mut self, reward_id: u64) -> Promise {
+        let reward = self.rewards.get(&reward_id).expect("No reward");
+
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == reward.receiver_id
+                || caller == self.owner_id
+                || caller == self.marketplace_contract_id,
+            "only the rewarded buyer, store owner or marketplace"
+        );
+        assert_eq!(reward.status, RewardStatus::Failed, "Only failed rewards can be retried");
+
+        self.internal_send_reward(reward_id)
+    }
+
+    /// Use any NEP-141 token as the store reward currency, including the token deployed with `deploy_token`.
+    /// The token's `ft_metadata` is checked before it is registered. Accrued rewards are paid in the current reward token.
+    /// The token can't be switched while rewards in the previous token are still being delivered.
+    pub fn set_reward_token(&mut self, token_account_id: AccountId) -> Promise {
+        self.assert_contract_owner();
+        self.internal_assert_no_outstanding_rewards();
+        self.internal_check_reward_token(token_account_id)
+    }
+
+    #[private]
+    pub fn set_reward_token_callback(&mut self, token_account_id: AccountId) -> RewardToken {
+        // Ensure the token answered with valid NEP-148 metadata
+        let metadata = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<FungibleTokenMetadata>(&value)
+                    .unwrap_or_else(|_| env::panic_str("Invalid fungible token metadata"))
+            }
+            _ => env::panic_str("Could not get the fungible token metadata"),
+        };
+        require!(
+            metadata.spec.starts_with("ft-1"),
+            "Reward token must implement the NEP-141 fungible token standard"
+        );
+
+        //a reward may have been claimed while the metadata was checked
+        self.internal_assert_no_outstanding_rewards();
+
+        let reward_token = RewardToken {
+            account_id: token_account_id,
+            metadata,
+        };
+        self.reward_token = Some(reward_token.clone());
+        env::log_str(&format!("Reward token set to {}", reward_token.account_id));
+
+        reward_token
+    }
+
+    /// Get the token used as the store reward currency
+    pub fn get_reward_token(&self) -> Option<RewardToken> {
+        self.reward_token.clone()
+    }
+
+    /// Set the store policy used to compute the reward of each purchase. Passing in none falls back to
+    /// the fixed `reward_amount_per_unit` of each series.
+    pub fn set_reward_policy(&mut self, policy: Option<RewardPolicy>) {
+        self.assert_contract_owner();
+
+        match policy {
+            Some(policy) => {
+                assert!(
+                    policy.multipliers.iter().all(|m| m.starts_at < m.ends_at),
+                    "Reward multiplier must start before it ends"
+                );
+                self.reward_policy.set(&policy);
+            }
+            None => {
+                self.reward_policy.remove();
+            }
+        }
+    }
+
+    /// Get the store reward policy
+    pub fn get_reward_policy(&self) -> Option<RewardPolicy> {
+        self.reward_policy.get()
+    }
+
+    /// Preview the price a buyer pays for a token of the series right now, after their loyalty or stake discount.
+    /// The marketplace charges this price at checkout, none if the series has no price
+    pub fn preview_price(&self, id: U64, buyer_id: AccountId) -> Option<U128> {
+        let series = self.series_by_id.get(&id.0).expect("Not a series");
+        self.internal_price_for(&series, &buyer_id).map(U128)
+    }
+
+    /// Preview the store token reward a buyer would get for buying a token of the series right now
+    pub fn preview_reward(&self, id: U64, buyer_id: AccountId) -> U128 {
+        let series = self.series_by_id.get(&id.0).expect("Not a series");
+        let price = self.internal_price_for(&series, &buyer_id).unwrap_or(0);
+
+        U128(self.internal_compute_reward(&series, &buyer_id, price))
+    }
+
+    /// Set the total store tokens that can be rewarded across the store. Passing in none removes the limit
+    pub fn set_reward_budget(&mut self, budget: Option<U128>) {
+        self.assert_contract_owner();
+        self.reward_budget = budget.map(|b| b.into());
+    }
+
+    /// Set the total store tokens that can be rewarded for a series. Can be called by the series or contract owner.
+    /// Passing in none removes the limit
+    pub fn set_series_reward_budget(&mut self, id: U64, budget: Option<U128>) {
         let mut series = self.series_by_id.get(&id.0).expect("Not a series");
 
-        assert_eq!(series.metadata.is_reward, true, "There's no token reward for this product");
-
-        let token_quantity = series.metadata.reward_amount_per_unit;
-        let mem = format!("Thank You for Shopping at {:?}!", env::current_account_id());
-        let current_account = env::current_account_id().to_string();
-        let token_account: AccountId = format!("ft.{current_account}").parse().unwrap();
-        let storage_args = serde_json::to_vec(&StorageData {
-            account_id: receiver_id.clone(),
-            registration_only: false,
-        })
-            .unwrap();
-
-        let token_args = serde_json::to_vec(&TokenData {
-            receiver_id: receiver_id.clone(),
-            amount: token_quantity,
-            memo: mem,
-        })
-            .unwrap();
-
-        Promise::new(token_account.clone())
-            .function_call(
-                "storage_deposit".to_owned(),
-                storage_args,
-                ONE_YOCTO,
-                GAGAS,
-            )
-            .function_call(
-                "ft_transfer".to_owned(),
-                token_args,
-                NO_DEPOSIT,
-                GAGAS,
-            )
-            .then(
-                Self::ext(env::current_account_id())
-                    .reward_with_token_callback(token_quantity.clone()),
-            )
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == series.owner_id || caller == self.owner_id,
+            "only the series or contract owner"
+        );
+
+        series.reward_budget = budget.map(|b| b.into());
+        self.series_by_id.insert(&id.0, &series);
+    }
+
+    /// Get the remaining store tokens that can be rewarded across the store, unlimited if none
+    pub fn get_reward_budget(&self) -> Option<U128> {
+        self.reward_budget.map(U128)
+    }
+
+    /// Get the remaining store tokens that can be rewarded for a series, unlimited if none
+    pub fn get_series_reward_budget(&self, id: U64) -> Option<U128> {
+        let series = self.series_by_id.get(&id.0).expect("Not a series");
+        series.reward_budget.map(U128)
     }
 
     #[private]
-    pub fn reward_with_token_callback(&self, token_quantity: U128) -> String {
-        let token: u128 = token_quantity.into();
+    pub fn reward_with_token_callback(&mut self, reward_id: u64) -> PendingReward {
+        let mut reward = self.rewards.get(&reward_id).expect("No reward");
         if is_promise_success() {
-            let res = format!("Sent {token} token successfully!");
-
-            res
+            // The receiver is now registered on the token, no need to pay for its storage again
+            if let Some(token) = &self.reward_token {
+                self.reward_token_accounts
+                    .insert(&(token.account_id.clone(), reward.receiver_id.clone()));
+            }
+            self.outstanding_rewards.remove(&reward_id);
+            reward.status = RewardStatus::Delivered;
+            env::log_str(&format!("Sent {} token successfully!", reward.amount.0));
         } else {
-            let res = format!("failed sending token");
-
-            res
+            if let Some(token) = self.reward_token.clone() {
+                self.internal_restore_treasury(&token.account_id, reward.amount.0);
+            }
+            reward.status = RewardStatus::Failed;
+            env::log_str("failed sending token");
         }
+        self.rewards.insert(&reward_id, &reward);
+
+        reward
+    }
+
+    /// Get a reward
+    pub fn get_reward(&self, reward_id: u64) -> Option<PendingReward> {
+        self.rewards.get(&reward_id)
+    }
+
+    /// Paginate through the rewards that were not delivered yet
+    pub fn get_outstanding_rewards(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<PendingReward> {
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        self.outstanding_rewards
+            .iter()
+            //skip to the index we specified in the start variable
+            .skip(start as usize)
+            //take the first "limit" elements in the vector. If we didn't specify a limit, use 50
+            .take(limit.unwrap_or(50) as usize)
+            .map(|reward_id| self.rewards.get(&reward_id).unwrap())
+            .collect()
+    }
+
+    /// Get the rewards of an account that were not delivered yet
+    pub fn get_outstanding_rewards_for_account(&self, account_id: AccountId) -> Vec<PendingReward> {
+        self.outstanding_rewards
+            .iter()
+            .map(|reward_id| self.rewards.get(&reward_id).unwrap())
+            .filter(|reward| reward.receiver_id == account_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    //store with a reward token, rewards expiring after 1000 ms and 100 tokens accrued by the buyer at 0 ms
+    fn rewarded_store() -> Contract {
+        let mut contract = new_contract();
+        contract.reward_token = Some(RewardToken {
+            account_id: account("ft.store.near"),
+            metadata: FungibleTokenMetadata {
+                spec: "ft-1.0.0".to_string(),
+                name: "Store".to_string(),
+                symbol: "STR".to_string(),
+                icon: None,
+                reference: None,
+                reference_hash: None,
+                decimals: 18,
+            },
+        });
+        set_context(OWNER, 0);
+        contract.set_reward_expiry(Some(1_000));
+        contract.set_reward_budget(Some(U128(1_000)));
+        contract.internal_accrue_reward(&account("buyer.near"), 100);
+        contract
+    }
+
+    #[test]
+    fn claim_rewards_sends_balance() {
+        let mut contract = rewarded_store();
+
+        set_time("buyer.near", 0, 500);
+        assert!(matches!(contract.claim_rewards(), PromiseOrValue::Promise(_)));
+
+        assert_eq!(contract.get_reward_balance(account("buyer.near")).amount, U128(0));
+        let rewards = contract.get_outstanding_rewards_for_account(account("buyer.near"));
+        assert_eq!(rewards.len(), 1);
+        assert_eq!(rewards[0].amount, U128(100));
+    }
+
+    #[test]
+    fn claim_rewards_expires_stale_balance() {
+        let mut contract = rewarded_store();
+
+        set_time("buyer.near", 0, 2_000);
+        assert!(matches!(contract.claim_rewards(), PromiseOrValue::Value(None)));
+
+        assert_eq!(contract.get_reward_balance(account("buyer.near")).amount, U128(0));
+        assert!(contract.get_outstanding_rewards_for_account(account("buyer.near")).is_empty());
+        assert_eq!(contract.get_reward_budget(), Some(U128(1_100)));
+    }
+
+    #[test]
+    fn accrual_does_not_revive_expired_rewards() {
+        let mut contract = rewarded_store();
+
+        set_time("buyer.near", 0, 2_000);
+        contract.internal_accrue_reward(&account("buyer.near"), 50);
+
+        assert_eq!(contract.get_reward_balance(account("buyer.near")).amount, U128(50));
+        assert_eq!(contract.get_reward_budget(), Some(U128(1_100)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Rewards in the current reward token must be delivered or retried first")]
+    fn reward_token_is_kept_while_rewards_are_outstanding() {
+        let mut contract = rewarded_store();
+        set_time("buyer.near", 0, 500);
+        contract.claim_rewards();
+
+        set_context(OWNER, 0);
+        contract.set_reward_token(account("other.store.near"));
+    }
+
+    //free token of a series rewarding 10 store tokens per unit, minted to the buyer
+    fn free_reward_token(contract: &mut Contract) -> (u64, TokenId) {
+        let series_id = create_series(contract, None, None, None);
+        let mut series = contract.series_by_id.get(&series_id).unwrap();
+        series.metadata.is_reward = true;
+        series.metadata.reward_amount_per_unit = U128(10);
+        contract.series_by_id.insert(&series_id, &series);
+        let token_id = mint(contract, series_id, "buyer.near");
+        (series_id, token_id)
+    }
+
+    #[test]
+    fn reward_without_receipt_is_issued_once() {
+        let mut contract = rewarded_store();
+        let (series_id, token_id) = free_reward_token(&mut contract);
+
+        set_time(MARKETPLACE, 0, 500);
+        assert_eq!(contract.reward_with_token(U64(series_id), account("buyer.near"), Some(token_id.clone())), U128(10));
+        assert_eq!(contract.get_reward_balance(account("buyer.near")).amount, U128(110));
+        assert!(contract.receipts.get(&token_id).unwrap().reward_issued);
+    }
+
+    #[test]
+    #[should_panic(expected = "Token reward was already issued")]
+    fn reward_without_receipt_refuses_second_issue() {
+        let mut contract = rewarded_store();
+        let (series_id, token_id) = free_reward_token(&mut contract);
+
+        set_time(MARKETPLACE, 0, 500);
+        contract.reward_with_token(U64(series_id), account("buyer.near"), Some(token_id.clone()));
+        contract.reward_with_token(U64(series_id), account("buyer.near"), Some(token_id));
+    }
+
+    #[test]
+    #[should_panic(expected = "Token ID is required")]
+    fn reward_needs_a_token() {
+        let mut contract = rewarded_store();
+        let (series_id, _) = free_reward_token(&mut contract);
+
+        set_time(MARKETPLACE, 0, 500);
+        contract.reward_with_token(U64(series_id), account("buyer.near"), None);
+    }
+
+    #[test]
+    fn reward_policy() {
+        let policy = RewardPolicy {
+            per_near_spent: U128(10),
+            first_purchase_bonus: U128(50),
+            category_rates: HashMap::from([("shoes".to_string(), U128(5))]),
+            multipliers: vec![RewardMultiplier { multiplier: 20_000, starts_at: 100, ends_at: 200 }],
+        };
+        let shoes = "shoes".to_string();
+        assert_eq!(policy.reward(1, None, 2 * ONE_NEAR, false, 0), 21);
+        assert_eq!(policy.reward(1, Some(&shoes), 2 * ONE_NEAR, false, 0), 25);
+        assert_eq!(policy.reward(1, None, 2 * ONE_NEAR, true, 0), 71);
+        assert_eq!(policy.reward(1, None, 2 * ONE_NEAR, true, 150), 142);
+        assert_eq!(policy.reward(1, None, 2 * ONE_NEAR, true, 200), 71);
     }
-}
\ No newline at end of file
+}
@@ -16,6 +16,81 @@ pub trait NonFungibleTokenCore {
     ) -> Payout;
 }
 
+#[near_bindgen]
+impl Contract {
+    /// Set the limits on the total basis points and number of recipients of series royalties.
+    /// Can only be called by the marketplace.
+    pub fn set_royalty_limits(&mut self, royalty_limits: RoyaltyLimits) {
+        self.assert_marketplace_contract();
+        assert!(
+            royalty_limits.max_total <= 10_000,
+            "Royalty total cannot be above 10000"
+        );
+        self.royalty_limits = royalty_limits;
+    }
+
+    /// Change the royalty recipients of a series. Can only be called by the series owner.
+    /// The total royalty can't be raised above the lowest royalty a token of the series was minted with,
+    /// or the royalty the series was created with before the first mint.
+    #[payable]
+    pub fn update_series_royalty(&mut self, id: U64, royalty: Option<HashMap<AccountId, u32>>) {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let mut series = self.series_by_id.get(&id.0).expect("Not a series");
+        assert_eq!(series.owner_id, env::predecessor_account_id(), "only the series owner");
+
+        if let Some(royalty) = &royalty {
+            self.internal_assert_royalty_update(series.max_royalty, royalty);
+        }
+
+        self.internal_log_royalty_update(id.0, None, &royalty);
+        series.royalty = royalty;
+        self.series_by_id.insert(&id.0, &series);
+
+        //refund any excess storage if the user attached too much. Panic if they didn't attach enough to cover the required.
+        refund_deposit(env::storage_usage().saturating_sub(initial_storage_usage));
+    }
+
+    /// Override the royalty of a single token, passing in none goes back to the series royalty.
+    /// Can only be called by the series owner, within the royalty of the token at mint time.
+    #[payable]
+    pub fn set_token_royalty(&mut self, token_id: TokenId, royalty: Option<HashMap<AccountId, u32>>) {
+        // Measure the initial storage being used on the contract
+        let initial_storage_usage = env::storage_usage();
+
+        let token = self.tokens_by_id.get(&token_id).expect("No token");
+        let series = self.series_by_id.get(&token.series_id).expect("Not a series");
+        assert_eq!(series.owner_id, env::predecessor_account_id(), "only the series owner");
+
+        match &royalty {
+            Some(royalty) => {
+                self.internal_assert_royalty_update(token.royalty_at_mint, royalty);
+                self.token_royalties.insert(&token_id, royalty);
+            }
+            None => {
+                self.token_royalties.remove(&token_id);
+            }
+        }
+        self.internal_log_royalty_update(token.series_id, Some(token_id), &royalty);
+
+        //refund any excess storage if the user attached too much. Panic if they didn't attach enough to cover the required.
+        refund_deposit(env::storage_usage().saturating_sub(initial_storage_usage));
+    }
+
+    /// Get the royalty of a token, its own override or the royalty of its series
+    pub fn get_token_royalty(&self, token_id: TokenId) -> Option<HashMap<AccountId, u32>> {
+        let token = self.tokens_by_id.get(&token_id).expect("No token");
+        let series = self.series_by_id.get(&token.series_id).expect("Not a series");
+        self.internal_royalty_for(&token_id, &series)
+    }
+
+    /// Get the limits on series royalties
+    pub fn get_royalty_limits(&self) -> RoyaltyLimits {
+        self.royalty_limits.clone()
+    }
+}
+
 #[near_bindgen]
 impl NonFungibleTokenCore for Contract {
     //calculates the payout for a token given the passed in balance. This is a view method
@@ -23,62 +98,17 @@ impl NonFungibleTokenCore for Contract {
         //get the token object
         let token = self.tokens_by_id.get(&token_id).expect("No token");
 
-        //get the owner of the token
-        let owner_id = token.owner_id;
-        //keep track of the total perpetual royalties
-        let mut total_perpetual = 0;
-        //get the u128 version of the passed in balance (which was U128 before)
-        let balance_u128 = u128::from(balance);
-        //keep track of the payout object to send back
-        let mut payout_object = Payout {
-            payout: HashMap::new(),
-        };
         //get the royalty object from series
         let cur_series = self
             .series_by_id
             .get(&token.series_id)
             .expect("Not a series");
 
-        // If the series doesn't have a royalty, we'll return an a payout object that just includes the owner
-        let royalty_option = cur_series.royalty;
-        if royalty_option.is_none() {
-            let mut payout = HashMap::new();
-            payout.insert(owner_id, balance);
-            return Payout {
-                payout: payout
-            };
-        }
-        // Otherwise, we will get the royalty object from the series
-        let royalty = royalty_option.unwrap();
-
-        //make sure we're not paying out to too many people (GAS limits this)
-        assert!(
-            royalty.len() as u32 <= max_len_payout,
-            "Market can&not payout to that many receivers"
-        );
-
-        //go through each key and value in the royalty object
-        for (k, v) in royalty.iter() {
-            //get the key
-            let key = k.clone();
-            //only insert into the payout if the key isn't the token owner (we add their payout at the end)
-            if key != owner_id {
-                //
-                payout_object
-                    .payout
-                    .insert(key, royalty_to_payout(*v, balance_u128));
-                total_perpetual += *v;
-            }
-        }
+        //the token's own royalty override is used before the series royalty
+        let royalty = self.internal_royalty_for(&token_id, &cur_series);
 
-        // payout to previous owner who gets 100% - total perpetual royalties
-        payout_object.payout.insert(
-            owner_id,
-            royalty_to_payout(10000 - total_perpetual, balance_u128),
-        );
-
-        //return the payout object
-        payout_object
+        //pay out the royalty with the remainder going to the owner of the token
+        Payout::from_royalty(&royalty, &token.owner_id, balance.0, max_len_payout)
     }
 
     //transfers the token to the receiver ID and returns the payout object that should be payed given the passed in balance.
@@ -96,6 +126,12 @@ impl NonFungibleTokenCore for Contract {
         assert_one_yocto();
         //get the sender ID
         let sender_id = env::predecessor_account_id();
+
+        //ensure the sale price is within the resale price cap of the series
+        let token = self.tokens_by_id.get(&token_id).expect("No token");
+        let cur_series = self.series_by_id.get(&token.series_id).expect("Not a series");
+        self.internal_assert_resale_price(&cur_series, balance.0);
+
         //transfer the token to the passed in receiver and get the previous token object back
         let previous_token =
             self.internal_transfer(&sender_id, &receiver_id, &token_id, Some(approval_id), memo);
@@ -106,62 +142,135 @@ impl NonFungibleTokenCore for Contract {
             &previous_token.approved_account_ids,
         );
 
-        //get the owner of the token
-        let owner_id = previous_token.owner_id;
-        //keep track of the total perpetual royalties
-        let mut total_perpetual = 0;
-        //get the u128 version of the passed in balance (which was U128 before)
-        let balance_u128 = u128::from(balance);
-        //keep track of the payout object to send back
-        let mut payout_object = Payout {
-            payout: HashMap::new(),
-        };
+        //the token's own royalty override is used before the series royalty
+        let royalty = self.internal_royalty_for(&token_id, &cur_series);
 
-        //get the royalty object from series
-        let cur_series = self
-            .series_by_id
-            .get(&previous_token.series_id)
-            .expect("Not a series");
+        //pay out the royalty with the remainder going to the previous owner of the token
+        Payout::from_royalty(&royalty, &previous_token.owner_id, balance.0, max_len_payout)
+    }
+}
 
-        // If the series doesn't have a royalty, we'll return an a payout object that just includes the owner
-        let royalty_option = cur_series.royalty;
-        if royalty_option.is_none() {
-            let mut payout = HashMap::new();
-            payout.insert(owner_id, balance);
-            return Payout {
-                payout: payout
-            };
-        }
-        // Otherwise, we will get the royalty object from the series
-        let royalty = royalty_option.unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{create_series, mint, new_contract, set_context, OWNER};
 
-        //make sure we're not paying out to too many people (GAS limits this)
-        assert!(
-            royalty.len() as u32 <= max_len_payout,
-            "Market cannot payout to that many receivers"
-        );
+    fn royalty(entries: &[(&str, u32)]) -> HashMap<AccountId, u32> {
+        entries
+            .iter()
+            .map(|(account_id, percentage)| (account_id.parse().unwrap(), *percentage))
+            .collect()
+    }
 
-        //go through each key and value in the royalty object
-        for (k, v) in royalty.iter() {
-            //get the key
-            let key = k.clone();
-            //only insert into the payout if the key isn't the token owner (we add their payout at the end)
-            if key != owner_id {
-                //
-                payout_object
-                    .payout
-                    .insert(key, royalty_to_payout(*v, balance_u128));
-                total_perpetual += *v;
-            }
-        }
+    #[test]
+    fn royalty_limits() {
+        let limits = RoyaltyLimits::default();
+        limits.assert_valid(&royalty(&[("alice.near", 2_500), ("bob.near", 2_500)]));
+    }
 
-        // payout to previous owner who gets 100% - total perpetual royalties
-        payout_object.payout.insert(
-            owner_id,
-            royalty_to_payout(10000 - total_perpetual, balance_u128),
-        );
+    #[test]
+    #[should_panic(expected = "Royalty of 2501 for bob.near brings the total to 5001, the maximum is 5000")]
+    fn royalty_limits_total() {
+        RoyaltyLimits::default().assert_valid(&royalty(&[("alice.near", 2_500), ("bob.near", 2_501)]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Royalty has 2 recipients, the maximum is 1")]
+    fn royalty_limits_recipients() {
+        let limits = RoyaltyLimits { max_total: 5_000, max_recipients: 1 };
+        limits.assert_valid(&royalty(&[("alice.near", 100), ("bob.near", 100)]));
+    }
+
+    fn account(account_id: &str) -> AccountId {
+        account_id.parse().unwrap()
+    }
+
+    fn total(payout: &Payout) -> Balance {
+        payout.payout.values().map(|amount| amount.0).sum()
+    }
+
+    #[test]
+    fn royalty_payout_sums_to_balance() {
+        let royalty = Some(HashMap::from([
+            (account("creator.near"), 333),
+            (account("designer.near"), 333),
+            (account("charity.near"), 333),
+        ]));
+        let payout = Payout::from_royalty(&royalty, &account("seller.near"), 1_001, 10);
+
+        assert_eq!(total(&payout), 1_001);
+        assert_eq!(payout.payout[&account("creator.near")], U128(33));
+        assert_eq!(payout.payout[&account("designer.near")], U128(33));
+        assert_eq!(payout.payout[&account("charity.near")], U128(33));
+        // the seller gets the rounding dust
+        assert_eq!(payout.payout[&account("seller.near")], U128(902));
+    }
+
+    #[test]
+    fn royalty_payout_seller_in_royalty() {
+        let royalty = Some(HashMap::from([
+            (account("creator.near"), 1_000),
+            (account("seller.near"), 500),
+        ]));
+        let payout = Payout::from_royalty(&royalty, &account("seller.near"), 10_000, 10);
+
+        assert_eq!(payout.payout.len(), 2);
+        assert_eq!(payout.payout[&account("creator.near")], U128(1_000));
+        assert_eq!(payout.payout[&account("seller.near")], U128(9_000));
+    }
+
+    #[test]
+    fn royalty_payout_without_royalty() {
+        let payout = Payout::from_royalty(&None, &account("seller.near"), 7, 0);
+        assert_eq!(payout.payout.len(), 1);
+        assert_eq!(payout.payout[&account("seller.near")], U128(7));
+    }
+
+    #[test]
+    #[should_panic(expected = "Market cannot payout to that many receivers")]
+    fn royalty_payout_max_len() {
+        let royalty = Some(HashMap::from([(account("creator.near"), 1_000)]));
+        Payout::from_royalty(&royalty, &account("seller.near"), 10_000, 0);
+    }
+
+    //series with a 10% creator royalty and a token minted at that royalty
+    fn minted_series(contract: &mut Contract) -> (u64, TokenId) {
+        let series_id = create_series(contract, Some(royalty(&[("creator.near", 1_000)])), None, None);
+        let token_id = mint(contract, series_id, "buyer.near");
+        set_context(OWNER, ONE_NEAR);
+        (series_id, token_id)
+    }
+
+    #[test]
+    fn royalty_update_within_mint_royalty() {
+        let mut contract = new_contract();
+        let (series_id, _) = minted_series(&mut contract);
+
+        contract.update_series_royalty(U64(series_id), Some(royalty(&[("creator.near", 500)])));
+        contract.update_series_royalty(U64(series_id), Some(royalty(&[("creator.near", 500), ("designer.near", 500)])));
+        assert_eq!(contract.series_by_id.get(&series_id).unwrap().max_royalty, 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Royalty total of 1500 is above the 1000 set at mint time")]
+    fn royalty_update_cannot_raise_series_royalty() {
+        let mut contract = new_contract();
+        let (series_id, _) = minted_series(&mut contract);
+
+        contract.update_series_royalty(U64(series_id), Some(royalty(&[("creator.near", 1_500)])));
+    }
+
+    #[test]
+    #[should_panic(expected = "Royalty total of 1000 is above the 500 set at mint time")]
+    fn token_royalty_capped_at_its_mint_royalty() {
+        let mut contract = new_contract();
+        let (series_id, first_token_id) = minted_series(&mut contract);
+        contract.update_series_royalty(U64(series_id), Some(royalty(&[("creator.near", 500)])));
+        let second_token_id = mint(&mut contract, series_id, "buyer.near");
 
-        //return the payout object
-        payout_object
+        //the first token was minted at 10% and can go back to it, the second one was minted at 5%
+        set_context(OWNER, ONE_NEAR);
+        contract.set_token_royalty(first_token_id, Some(royalty(&[("creator.near", 1_000)])));
+        contract.set_token_royalty(second_token_id, Some(royalty(&[("creator.near", 1_000)])));
     }
 }
@@ -8,6 +8,7 @@ impl Contract {
     /// If copies are set in the metadata, it will enforce that only that number of NFTs can be minted. If not, unlimited NFTs can be minted.
     /// If a title is set in the metadata, enumeration methods will return the `${title} - ${edition}` else, `${series_id} - ${edition}`
     /// All token IDs internally are stored as `${series_id}:${edition}`
+    /// The royalty applies to resales, the primary split to the first sale of each token with the remainder going to the creator.
     /// Caller must attach enough $NEAR to cover storage.
     #[payable]
     pub fn create_series(
@@ -15,6 +16,7 @@ impl Contract {
         metadata: TokenMetadata,
         colors: HashMap<String, u32>,
         royalty: Option<HashMap<AccountId, u32>>,
+        primary_split: Option<HashMap<AccountId, u32>>,
         price: Option<U128>
     ) {
         // Measure the initial storage being used on the contract
@@ -27,6 +29,20 @@ impl Contract {
             "only approved creators can add a type"
         );
 
+        // Ensure the royalty is within the limits of the store
+        if let Some(royalty) = &royalty {
+            self.royalty_limits.assert_valid(royalty);
+        }
+
+        // The primary split can give away the whole sale, within the same number of recipients
+        if let Some(primary_split) = &primary_split {
+            RoyaltyLimits {
+                max_total: 10_000,
+                max_recipients: self.royalty_limits.max_recipients,
+            }
+            .assert_valid(primary_split);
+        }
+
         let id: u64 = self.series_by_id.len() + 1;
 
         // Insert the series and ensure it doesn't already exist
@@ -38,7 +54,9 @@ impl Contract {
                         metadata,
                         affiliate: Some(HashMap::new()),
                         colors,
+                        max_royalty: royalty.as_ref().map_or(0, |royalty| royalty.values().sum()),
                         royalty,
+                        primary_split,
                         tokens: UnorderedSet::new(StorageKey::SeriesByIdInner {
                             // We get a new unique prefix for the collection
                             account_id_hash: hash_account_id(&format!(
@@ -48,6 +66,10 @@ impl Contract {
                         }),
                         owner_id: caller,
                         price: price.map(|p| p.into()),
+                        reward_budget: None,
+                        status: SeriesStatus::Live,
+                        members_only: false,
+                        resale_rules: None,
                     }
                 )
                 .is_none(),
@@ -63,17 +85,63 @@ impl Contract {
 
     /// Mint a new NFT that is part of a series. The caller must be an approved minter.
     /// The series ID must exist and if the metadata specifies a copy limit, you cannot exceed it.
+    /// An affiliate can be attributed either by account ID or by one of its referral codes.
+    /// Series with a primary split are settled by the store: the price and storage must be attached and the
+    /// receipt from `get_receipt` is flagged with `settled_by_store`.
     #[payable]
-    pub fn nft_mint(&mut self, id: U64, receiver_id: AccountId, attached_deposit: U128, color: String, affiliate: Option<AccountId>) {
+    pub fn nft_mint(&mut self, id: U64, receiver_id: AccountId, attached_deposit: U128, color: String, affiliate: Option<AccountId>, affiliate_code: Option<String>) {
         // Measure the initial storage being used on the contract
         let initial_storage_usage = env::storage_usage();
 
         // Get the series and how many tokens currently exist (edition number = cur_len + 1)
         let mut series = self.series_by_id.get(&id.0).expect("Not a series");
+
+        // Resolve the referral code to the affiliate account
+        let mut affiliate = self.internal_resolve_affiliate(id.0, affiliate, affiliate_code.as_ref());
+        let mut affiliate_code = affiliate_code;
+        let mut attributed_at = env::block_timestamp_ms();
+
+        // Fall back to the referral registered by the buyer within the attribution window
+        if affiliate.is_none() {
+            if let Some(referral) = self.internal_attributed_referral(&receiver_id, id.0, &series) {
+                affiliate = Some(referral.affiliate_id);
+                affiliate_code = referral.affiliate_code;
+                attributed_at = referral.registered_at;
+            }
+        }
+
+        // Refuse affiliates that were revoked for this series or are referring their own purchase
+        if let Some(affiliate_id) = &affiliate {
+            require!(
+                affiliate_id != &receiver_id,
+                "Affiliate cannot refer their own purchase"
+            );
+            require!(
+                !self.revoked_affiliates.contains(&(id.0, affiliate_id.clone())),
+                "Affiliate was revoked for this series"
+            );
+        }
         
+        // Series that aren't live can only be bought by loyalty members with early access
+        if series.status != SeriesStatus::Live {
+            require!(
+                self.internal_loyalty_tier(&receiver_id).is_some_and(|tier| tier.early_access),
+                "Series is not on sale yet"
+            );
+        }
+
+        // Members-only series can only be bought by store token stakers with members access
+        if series.members_only {
+            require!(
+                self.internal_stake_tier(&receiver_id).is_some_and(|tier| tier.members_access),
+                "Series is only available to members"
+            );
+        }
+
         // Check if the series has a price per token. If it does, ensure the caller has attached at least that amount
+        // Loyalty members and stakers pay the price minus their tier discount
         let mut price_per_token = 0; 
-        if let Some(price) = series.price {
+        if let Some(price) = self.internal_price_for(&series, &receiver_id) {
             price_per_token = price;
             require!(attached_deposit.0 > price_per_token, "Need to attach at least enough to cover price");
         // If the series doesn't have a price, ensure the caller is an approved minter.
@@ -86,94 +154,213 @@ impl Contract {
             );
         }
 
-        let cur_len = series.tokens.len();
-        // Ensure we haven't overflowed on the number of copies minted
-        if let Some(copies) = series.metadata.copies {
-            require!(
-                cur_len < copies,
-                "cannot mint anymore NFTs for the given series. Limit reached"
-            );
+        let token_id = self.internal_mint(id.0, &mut series, &receiver_id, color);
+
+        //calculate the required storage which was the used - initial
+        let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
+
+        // refund storage used
+        // refund_deposit(required_storage_in_bytes);
+
+        // If there's some price for the token, we'll payout the series owner. Otherwise, refund the excess deposit for storage to the caller
+        if price_per_token > 0 {
+            // Work out the store token reward of the purchase before the buyer becomes a customer
+            let reward_amount = self.internal_compute_reward(&series, &receiver_id, price_per_token);
+
+            let series_owner_id = series.owner_id.clone();
+            let mut receipt = self.marketplace_series_callback(id.clone(), required_storage_in_bytes, price_per_token, self.owner_id.clone(), series.owner_id, token_id, attached_deposit.clone(), affiliate);
+
+            // The affiliate commission is held by the store until the affiliate withdraws it
+            let commission = receipt.affiliate_commission.map_or(0, |commission| commission.0);
+            if let Some(affiliate_id) = &receipt.affiliate_id {
+                require!(
+                    env::attached_deposit() >= commission,
+                    "Must attach enough to cover the affiliate commission"
+                );
+                self.internal_credit_affiliate(affiliate_id, id.0, price_per_token, commission);
+            }
+
+            // The store pays out the primary split of the sale, after the affiliate commission, with the remainder going to the creator
+            if let Some(primary_split) = &series.primary_split {
+                receipt.primary_payout = Some(split_payout(primary_split, &series_owner_id, price_per_token - commission));
+                receipt.settled_by_store = true;
+            }
+
+            // Record the referral code used to attribute the sale and when the buyer was referred
+            if receipt.affiliate {
+                receipt.affiliate_code = affiliate_code.map(|code| code.to_lowercase());
+                receipt.affiliate_attributed_at = Some(attributed_at);
+            }
+
+            // Record the reward the buyer gets for this purchase and take it out of the reward budgets
+            if reward_amount > 0 {
+                self.internal_use_reward_budget(id.0, reward_amount);
+                receipt.reward_amount = Some(U128(reward_amount));
+            }
+
+            // Reward both parties when a buyer referred by another customer makes their first purchase
+            self.internal_buyer_referral_rewards(&receiver_id, &mut receipt);
+
+            self.internal_record_purchase(&receiver_id, price_per_token);
+            self.receipts.insert(&receipt.token_id, &receipt);
+
+            if let Some(payout) = &receipt.primary_payout {
+                // The price and the storage of the mint, receipt and ledgers must be attached when the store settles the sale
+                let required_cost =
+                    env::storage_byte_cost() * Balance::from(env::storage_usage() - initial_storage_usage);
+                assert!(
+                    env::attached_deposit() >= price_per_token + required_cost,
+                    "Must attach {} yoctoNEAR to cover the price and storage to settle the primary sale split",
+                    price_per_token + required_cost
+                );
+                for (account_id, amount) in payout.iter() {
+                    if amount.0 > 0 {
+                        Promise::new(account_id.clone()).transfer(amount.0);
+                    }
+                }
+
+                let refund = env::attached_deposit() - price_per_token - required_cost;
+                if refund > 1 {
+                    Promise::new(env::predecessor_account_id()).transfer(refund);
+                }
+            }
+        } else {
+            refund_deposit(required_storage_in_bytes);
         }
+    }
 
+    /// Set the sale status of a series. Can be called by the series or contract owner.
+    /// Series in a draft or pre-sale state can only be bought by loyalty members with early access.
+    pub fn set_series_status(&mut self, id: U64, status: SeriesStatus) {
+        let mut series = self.series_by_id.get(&id.0).expect("Not a series");
+
+        let caller = env::predecessor_account_id();
         assert!(
-            series.colors.contains_key(&color),
-            "Color is not present"
+            caller == series.owner_id || caller == self.owner_id,
+            "only the series or contract owner"
         );
 
-        if let Some(cur_color_len) = series.colors.get(&color) {
-            assert!(
-                cur_color_len >= &1,
-                "This color for product is not available, choose another color"
-            );
+        series.status = status;
+        self.series_by_id.insert(&id.0, &series);
+    }
 
-            series.colors.insert(color, cur_color_len - &1);
-        }
+    /// Restrict a series to store token stakers with members access. Can be called by the series or contract owner.
+    pub fn set_series_members_only(&mut self, id: U64, members_only: bool) {
+        let mut series = self.series_by_id.get(&id.0).expect("Not a series");
 
-        // The token ID is stored internally as `${series_id}:${edition}`
-        let token_id = format!("{}:{}", id.0, cur_len + 1);
-        series.tokens.insert(&token_id);
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == series.owner_id || caller == self.owner_id,
+            "only the series or contract owner"
+        );
+
+        series.members_only = members_only;
         self.series_by_id.insert(&id.0, &series);
+    }
 
-        //specify the token struct that contains the owner ID
-        let token = Token {
-            // Series ID that the token belongs to
-            series_id: id.0,
-            //set the owner ID equal to the receiver ID passed into the function
-            owner_id: receiver_id,
-            //we set the approved account IDs to the default value (an empty map)
-            approved_account_ids: Default::default(),
-            //the next approval ID is set to 0
-            next_approval_id: 0,
-        };
-
-        //insert the token ID and token struct and make sure that the token doesn't exist
-        require!(
-            self.tokens_by_id.insert(&token_id, &token).is_none(),
-            "Token already exists"
+    /// Set the anti-scalping rules of a series: a maximum resale price relative to the series price,
+    /// a minimum hold period before transfers and a per-account holding limit. Passing in none removes them.
+    /// Can be called by the series or contract owner.
+    pub fn set_series_resale_rules(&mut self, id: U64, resale_rules: Option<ResaleRules>) {
+        let mut series = self.series_by_id.get(&id.0).expect("Not a series");
+
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == series.owner_id || caller == self.owner_id,
+            "only the series or contract owner"
         );
 
-        //call the internal method for adding the token to the owner
-        self.internal_add_token_to_owner(&token.owner_id, &token_id);
-
-        let marketplace_id = self.marketplace_contract_id.clone();
-
-        //call the internal method for approving marketplace to transfer token
-        self.internal_approve_token_marketplace(&marketplace_id, &token_id);
-
-        //Lock the token pending marketplace transaction completion
-        assert_eq!(self.tokens_locked.insert(&token_id), true);
-
-        // Construct the mint log as per the events standard.
-        let nft_mint_log: EventLog = EventLog {
-            // Standard name ("nep171").
-            standard: NFT_STANDARD_NAME.to_string(),
-            // Version of the standard ("nft-1.0.0").
-            version: NFT_METADATA_SPEC.to_string(),
-            // The data related with the event stored in a vector.
-            event: EventLogVariant::NftMint(vec![NftMintLog {
-                // Owner of the token.
-                owner_id: token.owner_id.to_string(),
-                // Vector of token IDs that were minted.
-                token_ids: vec![token_id.to_string()],
-                // An optional memo to include.
-                memo: None,
-            }]),
-        };
-
-        // Log the serialized json.
-        env::log_str(&nft_mint_log.to_string());
+        if let Some(rules) = &resale_rules {
+            //the maximum resale price is relative to the series price, free series have nothing to cap it to
+            if rules.max_resale_price.is_some() {
+                require!(series.price.is_some(), "Series has no price to cap resales to");
+            }
+            if let Some(max_per_account) = rules.max_per_account {
+                require!(max_per_account > 0, "Holding limit must be above 0");
+            }
+            if let Some(min_hold_period) = rules.min_hold_period {
+                assert!(
+                    min_hold_period <= MAX_HOLD_PERIOD,
+                    "Hold period cannot be above {} ms",
+                    MAX_HOLD_PERIOD
+                );
+            }
+        }
 
-        //calculate the required storage which was the used - initial
-        let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
+        series.resale_rules = resale_rules;
+        self.series_by_id.insert(&id.0, &series);
+    }
+}
 
-        // refund storage used
-        // refund_deposit(required_storage_in_bytes);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
 
-        // If there's some price for the token, we'll payout the series owner. Otherwise, refund the excess deposit for storage to the caller
-        if price_per_token > 0 {
-            self.marketplace_series_callback(id.clone(), required_storage_in_bytes, price_per_token, self.owner_id.clone(), series.owner_id, token_id, attached_deposit.clone(), affiliate);
-        } else {
-            refund_deposit(required_storage_in_bytes);
-        }
+    const PRICE: Balance = 10 * ONE_NEAR;
+
+    fn split_series(contract: &mut Contract) -> u64 {
+        let split = HashMap::from([(account("designer.near"), 2_000), (account("charity.near"), 500)]);
+        create_series(contract, None, Some(split), Some(PRICE))
+    }
+
+    #[test]
+    fn mint_settles_primary_split() {
+        let mut contract = new_contract();
+        let series_id = split_series(&mut contract);
+
+        set_context(MARKETPLACE, PRICE + ONE_NEAR);
+        contract.nft_mint(U64(series_id), account("buyer.near"), U128(PRICE + ONE_NEAR), "red".to_string(), None, None);
+
+        let receipt = contract.get_receipt(format!("{}:1", series_id)).unwrap();
+        assert!(receipt.settled_by_store);
+        let payout = receipt.primary_payout.unwrap();
+        assert_eq!(payout[&account("designer.near")], U128(2 * ONE_NEAR));
+        assert_eq!(payout[&account("charity.near")], U128(ONE_NEAR / 2));
+        assert_eq!(payout[&account(OWNER)], U128(PRICE - 2 * ONE_NEAR - ONE_NEAR / 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "to cover the price and storage to settle the primary sale split")]
+    fn mint_split_needs_price_and_storage() {
+        let mut contract = new_contract();
+        let series_id = split_series(&mut contract);
+
+        //the price alone doesn't cover the storage of the token and receipt
+        set_context(MARKETPLACE, PRICE);
+        contract.nft_mint(U64(series_id), account("buyer.near"), U128(PRICE + ONE_NEAR), "red".to_string(), None, None);
+    }
+
+    #[test]
+    fn mint_without_split_is_settled_by_marketplace() {
+        let mut contract = new_contract();
+        let series_id = create_series(&mut contract, None, None, Some(PRICE));
+
+        set_context(MARKETPLACE, 0);
+        contract.nft_mint(U64(series_id), account("buyer.near"), U128(PRICE + ONE_NEAR), "red".to_string(), None, None);
+
+        let receipt = contract.get_receipt(format!("{}:1", series_id)).unwrap();
+        assert!(!receipt.settled_by_store);
+        assert!(receipt.primary_payout.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Hold period cannot be above")]
+    fn resale_rules_bound_hold_period() {
+        let mut contract = new_contract();
+        let series_id = create_series(&mut contract, None, None, None);
+
+        let rules = ResaleRules { max_resale_price: None, min_hold_period: Some(u64::MAX), max_per_account: None };
+        contract.set_series_resale_rules(U64(series_id), Some(rules));
+    }
+
+    #[test]
+    #[should_panic(expected = "Series has no price to cap resales to")]
+    fn resale_rules_max_price_needs_a_series_price() {
+        let mut contract = new_contract();
+        let series_id = create_series(&mut contract, None, None, None);
+
+        let rules = ResaleRules { max_resale_price: Some(20_000), min_hold_period: None, max_per_account: None };
+        contract.set_series_resale_rules(U64(series_id), Some(rules));
     }
 }
@@ -0,0 +1,355 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Set the store stake tiers. Stakers reach a tier once one of their positions is above the tier's
+    /// minimum amount and lock period. Discounts are in basis points.
+    pub fn set_stake_tiers(&mut self, tiers: Vec<StakeTier>) {
+        self.assert_contract_owner();
+
+        if tiers.is_empty() {
+            self.stake_tiers.remove();
+            return;
+        }
+
+        // a 100% discount would make priced series free to mint by anyone
+        assert!(
+            tiers.iter().all(|tier| tier.discount < 10_000),
+            "Stake discount must be below 10000"
+        );
+
+        // keep the tiers sorted by amount
+        let mut tiers = tiers;
+        tiers.sort_by_key(|tier| (tier.min_amount.0, tier.min_lock_duration));
+        self.stake_tiers.set(&tiers);
+    }
+
+    /// Set how long (in milliseconds) unstaked tokens wait before they can be withdrawn. Passing in none disables the cooldown
+    pub fn set_unstake_cooldown(&mut self, unstake_cooldown: Option<u64>) {
+        self.assert_contract_owner();
+        self.unstake_cooldown = unstake_cooldown;
+    }
+
+    /// Deposit NEAR to pay for the storage of stake positions. Can be called for another account.
+    #[payable]
+    pub fn stake_storage_deposit(&mut self, account_id: Option<AccountId>) -> U128 {
+        let initial_storage_usage = env::storage_usage();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let deposit = self.stake_storage.get(&account_id).unwrap_or(0);
+        self.stake_storage.insert(&account_id, &deposit);
+
+        //the first deposit also pays for its own entry
+        let required_cost =
+            env::storage_byte_cost() * Balance::from(env::storage_usage() - initial_storage_usage);
+        assert!(
+            env::attached_deposit() > required_cost,
+            "Must attach more than {} yoctoNEAR",
+            required_cost,
+        );
+        let deposit = deposit + env::attached_deposit() - required_cost;
+        self.stake_storage.insert(&account_id, &deposit);
+
+        U128(deposit)
+    }
+
+    /// Withdraw the stake storage deposit that isn't used by stake positions
+    #[payable]
+    pub fn stake_storage_withdraw(&mut self) -> U128 {
+        //assert that the user attached exactly 1 yoctoNEAR for security reasons
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let deposit = self.stake_storage.get(&account_id).unwrap_or(0);
+        require!(deposit > 0, "No stake storage deposit to withdraw");
+
+        self.stake_storage.insert(&account_id, &0);
+        Promise::new(account_id).transfer(deposit);
+
+        U128(deposit)
+    }
+
+    /// Stake store tokens sent with `ft_transfer_call`. The `msg` can set the lock period of the position,
+    /// e.g. `{"lock_duration": 2592000000}`, an empty `msg` stakes without a lock.
+    /// The position storage is paid from the sender's `stake_storage_deposit`, the tokens are sent back otherwise.
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        let token = self.store_token.as_ref().expect("Store has no token yet");
+        require!(
+            env::predecessor_account_id() == token.account_id,
+            "Only the store token can be staked"
+        );
+        require!(amount.0 > 0, "Stake amount must be above 0");
+        let initial_storage_usage = env::storage_usage();
+
+        let lock_duration = if msg.is_empty() {
+            0
+        } else {
+            serde_json::from_str::<StakeArgs>(&msg)
+                .expect("Invalid stake arguments")
+                .lock_duration
+        };
+
+        let position = StakePosition {
+            position_id: self.next_stake_position_id,
+            amount,
+            lock_duration,
+            staked_at: env::block_timestamp_ms(),
+            unstaked_at: None,
+        };
+        self.next_stake_position_id += 1;
+        self.total_staked += amount.0;
+
+        let mut positions = self.stake_positions.get(&sender_id).unwrap_or_default();
+        positions.push(position);
+        self.stake_positions.insert(&sender_id, &positions);
+        self.internal_charge_stake_storage(&sender_id, initial_storage_usage);
+
+        // keep all the tokens
+        PromiseOrValue::Value(U128(0))
+    }
+
+    /// Unstake a position once its lock period is over. The position stops giving perks and
+    /// can be withdrawn after the unstake cooldown.
+    pub fn unstake(&mut self, position_id: u64) -> StakePosition {
+        let initial_storage_usage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let mut positions = self.stake_positions.get(&account_id).expect("No stake positions");
+        let position = positions
+            .iter_mut()
+            .find(|position| position.position_id == position_id)
+            .expect("No stake position");
+
+        require!(position.is_active(), "Position is already unstaked");
+        let now = env::block_timestamp_ms();
+        require!(
+            now >= position.staked_at + position.lock_duration,
+            "Position is still locked"
+        );
+
+        position.unstaked_at = Some(now);
+        let position = position.clone();
+        self.stake_positions.insert(&account_id, &positions);
+        self.internal_charge_stake_storage(&account_id, initial_storage_usage);
+
+        position
+    }
+
+    /// Withdraw the store tokens of an unstaked position once the unstake cooldown is over
+    #[payable]
+    pub fn withdraw_stake(&mut self, position_id: u64) -> Promise {
+        //assert that the user attached exactly 1 yoctoNEAR for security reasons
+        assert_one_yocto();
+        let token = self.store_token.clone().expect("Store has no token yet");
+
+        let account_id = env::predecessor_account_id();
+        let mut positions = self.stake_positions.get(&account_id).expect("No stake positions");
+        let index = positions
+            .iter()
+            .position(|position| position.position_id == position_id)
+            .expect("No stake position");
+
+        let unstaked_at = positions[index].unstaked_at.expect("Position must be unstaked first");
+        require!(
+            env::block_timestamp_ms() >= unstaked_at + self.unstake_cooldown.unwrap_or(0),
+            "Unstake cooldown is not over"
+        );
+
+        // Remove the position before the transfer, it is restored if the transfer fails
+        let initial_storage_usage = env::storage_usage();
+        let position = positions.remove(index);
+        if positions.is_empty() {
+            self.stake_positions.remove(&account_id);
+        } else {
+            self.stake_positions.insert(&account_id, &positions);
+        }
+        self.total_staked -= position.amount.0;
+        let storage_released = env::storage_byte_cost()
+            * Balance::from(initial_storage_usage.saturating_sub(env::storage_usage()));
+
+        let token_args = serde_json::to_vec(&TokenData {
+            receiver_id: account_id.clone(),
+            amount: position.amount,
+            memo: "Store token stake withdrawal".to_string(),
+        })
+        .unwrap();
+
+        Promise::new(token.account_id)
+            .function_call("ft_transfer".to_owned(), token_args, 1, GAGAS)
+            .then(Self::ext(env::current_account_id()).withdraw_stake_callback(
+                account_id,
+                position,
+                U128(storage_released),
+            ))
+    }
+
+    #[private]
+    pub fn withdraw_stake_callback(&mut self, account_id: AccountId, position: StakePosition, storage_released: U128) -> bool {
+        if is_promise_success() {
+            // give the released storage back to the staker's deposit
+            let deposit = self.stake_storage.get(&account_id).unwrap_or(0);
+            self.stake_storage.insert(&account_id, &(deposit + storage_released.0));
+            env::log_str("Successful stake withdrawal");
+            true
+        } else {
+            // put the position back so that it can be withdrawn again
+            self.total_staked += position.amount.0;
+            let mut positions = self.stake_positions.get(&account_id).unwrap_or_default();
+            positions.push(position);
+            self.stake_positions.insert(&account_id, &positions);
+            env::log_str("failed stake withdrawal");
+            false
+        }
+    }
+
+    /// Get the store stake tiers
+    pub fn get_stake_tiers(&self) -> Vec<StakeTier> {
+        self.stake_tiers.get().unwrap_or_default()
+    }
+
+    /// Get the best stake tier reached by an account
+    pub fn get_stake_tier(&self, account_id: AccountId) -> Option<StakeTier> {
+        self.internal_stake_tier(&account_id)
+    }
+
+    /// Get the stake positions of an account
+    pub fn get_stake_positions(&self, account_id: AccountId) -> Vec<StakePosition> {
+        self.stake_positions.get(&account_id).unwrap_or_default()
+    }
+
+    /// Get the store tokens currently staked on the store
+    pub fn get_total_staked(&self) -> U128 {
+        U128(self.total_staked)
+    }
+
+    /// Get the store tokens held by the store outside of the staked funds
+    pub fn get_store_token_treasury(&self) -> U128 {
+        U128(self.store_token_treasury)
+    }
+
+    /// Get the stake storage deposit of an account that isn't used by stake positions
+    pub fn get_stake_storage_deposit(&self, account_id: AccountId) -> U128 {
+        U128(self.stake_storage.get(&account_id).unwrap_or(0))
+    }
+
+    /// Get the unstake cooldown in milliseconds
+    pub fn get_unstake_cooldown(&self) -> Option<u64> {
+        self.unstake_cooldown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const TOKEN: &str = "ft.store.near";
+
+    //store with a deployed token whose 1000 tokens are all held by the store
+    fn token_store() -> Contract {
+        let mut contract = new_contract();
+        contract.store_token = Some(StoreToken {
+            account_id: account(TOKEN),
+            total_supply: U128(1_000),
+            name: "Store".to_string(),
+            symbol: "STR".to_string(),
+            icon: String::new(),
+            code_version: "1.0.0".to_string(),
+            deploy_mode: DeployMode::Locked,
+        });
+        contract.store_token_treasury = 1_000;
+        contract
+    }
+
+    fn stake(contract: &mut Contract, amount: u128, msg: &str) {
+        set_time(TOKEN, 0, 0);
+        contract.ft_on_transfer(account("staker.near"), U128(amount), msg.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Stake discount must be below 10000")]
+    fn stake_discount_below_full_price() {
+        let mut contract = token_store();
+        contract.set_stake_tiers(vec![StakeTier {
+            name: "Member".to_string(),
+            min_amount: U128(100),
+            min_lock_duration: 0,
+            discount: 10_000,
+            members_access: false,
+        }]);
+    }
+
+    #[test]
+    #[should_panic(expected = "with stake_storage_deposit to cover the stake storage")]
+    fn stake_needs_storage_deposit() {
+        let mut contract = token_store();
+        stake(&mut contract, 100, "");
+    }
+
+    #[test]
+    fn stake_and_unstake() {
+        let mut contract = token_store();
+        set_context("staker.near", ONE_NEAR);
+        let deposit = contract.stake_storage_deposit(None);
+
+        stake(&mut contract, 100, r#"{"lock_duration": 1000}"#);
+        assert_eq!(contract.get_total_staked(), U128(100));
+        assert!(contract.get_stake_storage_deposit(account("staker.near")).0 < deposit.0);
+
+        let positions = contract.get_stake_positions(account("staker.near"));
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].amount, U128(100));
+        assert_eq!(positions[0].lock_duration, 1_000);
+
+        set_time("staker.near", 0, 1_000);
+        let position = contract.unstake(positions[0].position_id);
+        assert_eq!(position.unstaked_at, Some(1_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "Position is still locked")]
+    fn unstake_locked_position() {
+        let mut contract = token_store();
+        set_context("staker.near", ONE_NEAR);
+        contract.stake_storage_deposit(None);
+        stake(&mut contract, 100, r#"{"lock_duration": 1000}"#);
+
+        set_time("staker.near", 0, 999);
+        contract.unstake(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only 1000 store tokens are available outside of the staked funds")]
+    fn treasury_transfers_keep_staked_funds() {
+        let mut contract = token_store();
+        set_context("staker.near", ONE_NEAR);
+        contract.stake_storage_deposit(None);
+        stake(&mut contract, 100, "");
+
+        set_context(OWNER, 1);
+        contract.store_token_transfer(account("friend.near"), U128(1_001), None);
+    }
+
+    #[test]
+    fn stake_position_tier() {
+        let tier = StakeTier {
+            name: "Member".to_string(),
+            min_amount: U128(100),
+            min_lock_duration: 1_000,
+            discount: 500,
+            members_access: true,
+        };
+        let mut position = StakePosition {
+            position_id: 1,
+            amount: U128(100),
+            lock_duration: 1_000,
+            staked_at: 0,
+            unstaked_at: None,
+        };
+        assert!(position.reaches(&tier));
+
+        position.lock_duration = 999;
+        assert!(!position.reaches(&tier));
+
+        position.lock_duration = 1_000;
+        position.unstaked_at = Some(2_000);
+        assert!(!position.reaches(&tier));
+    }
+}
@@ -0,0 +1,95 @@
+use crate::*;
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::testing_env;
+
+pub(crate) const OWNER: &str = "owner.near";
+pub(crate) const MARKETPLACE: &str = "market.near";
+pub(crate) const STORE: &str = "store.near";
+
+pub(crate) fn account(account_id: &str) -> AccountId {
+    account_id.parse().unwrap()
+}
+
+//set up a call from an account with an attached deposit
+pub(crate) fn set_context(predecessor: &str, attached_deposit: Balance) {
+    testing_env!(VMContextBuilder::new()
+        .current_account_id(account(STORE))
+        .signer_account_id(account(predecessor))
+        .predecessor_account_id(account(predecessor))
+        .attached_deposit(attached_deposit)
+        .build());
+}
+
+//set up a call from an account at a block time in milliseconds
+pub(crate) fn set_time(predecessor: &str, attached_deposit: Balance, timestamp_ms: u64) {
+    testing_env!(VMContextBuilder::new()
+        .current_account_id(account(STORE))
+        .signer_account_id(account(predecessor))
+        .predecessor_account_id(account(predecessor))
+        .attached_deposit(attached_deposit)
+        .block_timestamp(timestamp_ms * 1_000_000)
+        .build());
+}
+
+pub(crate) fn new_contract() -> Contract {
+    set_context(OWNER, 0);
+    Contract::new_default_meta(
+        account(OWNER),
+        account(MARKETPLACE),
+        "Store".to_string(),
+        "STR".to_string(),
+        None, None, None, None, None, None, None, None, None, None, None, None, None,
+    )
+}
+
+pub(crate) fn series_metadata() -> TokenMetadata {
+    TokenMetadata {
+        title: Some("Sneakers".to_string()),
+        description: "Sneakers".to_string(),
+        media: "sneakers.png".to_string(),
+        media_hash: None,
+        copies: None,
+        buy_timeout: 0,
+        is_discount: false,
+        discount_percent: 0,
+        token_amount_per_unit: U128(0),
+        is_reward: false,
+        reward_amount_per_unit: U128(0),
+        category: None,
+        is_custom_user: false,
+        user: None,
+        issued_at: None,
+        expires_at: None,
+        starts_at: None,
+        updated_at: None,
+        extra: None,
+        reference: None,
+        reference_hash: None,
+    }
+}
+
+//create a series owned by the store owner in a single "red" color and return its ID
+pub(crate) fn create_series(
+    contract: &mut Contract,
+    royalty: Option<HashMap<AccountId, u32>>,
+    primary_split: Option<HashMap<AccountId, u32>>,
+    price: Option<Balance>,
+) -> u64 {
+    set_context(OWNER, ONE_NEAR);
+    contract.create_series(
+        series_metadata(),
+        HashMap::from([("red".to_string(), 100)]),
+        royalty,
+        primary_split,
+        price.map(U128),
+    );
+    contract.series_by_id.len()
+}
+
+//mint a token of a free series to the receiver as the marketplace and return its ID
+pub(crate) fn mint(contract: &mut Contract, series_id: u64, receiver_id: &str) -> TokenId {
+    set_context(MARKETPLACE, ONE_NEAR);
+    contract.nft_mint(U64(series_id), account(receiver_id), U128(0), "red".to_string(), None, None);
+    let series = contract.series_by_id.get(&series_id).unwrap();
+    format!("{}:{}", series_id, series.tokens.len())
+}